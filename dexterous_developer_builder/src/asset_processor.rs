@@ -0,0 +1,223 @@
+use std::{env, process::Stdio};
+
+use anyhow::{anyhow, bail};
+use camino::{Utf8Path, Utf8PathBuf};
+use dexterous_developer_types::{AssetProcessor, BuildDiagnostic, DiagnosticLevel};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use crate::{hash_cache::HashCache, types::HashedFileRecord};
+
+#[derive(Debug, Clone, Default)]
+pub struct AssetProcessors(Vec<(GlobSet, AssetProcessor)>);
+
+impl AssetProcessors {
+    pub fn new(processors: &[AssetProcessor]) -> Result<Self, globset::Error> {
+        let processors = processors
+            .iter()
+            .map(|processor| {
+                let mut builder = GlobSetBuilder::new();
+                for glob in processor.globs.iter() {
+                    builder.add(Glob::new(glob)?);
+                }
+                Ok((builder.build()?, processor.clone()))
+            })
+            .collect::<Result<Vec<_>, globset::Error>>()?;
+        Ok(Self(processors))
+    }
+
+    /// The processors that handle an asset, in the order they were configured
+    pub fn matching(&self, asset: &Utf8Path) -> Vec<AssetProcessor> {
+        self.0
+            .iter()
+            .filter(|(globs, _)| globs.is_match(asset))
+            .map(|(_, processor)| processor.clone())
+            .collect()
+    }
+}
+
+/// Source assets are only published if nothing processes them, or a processor asks for it
+pub fn publishes_source(processors: &[AssetProcessor]) -> bool {
+    processors.is_empty() || processors.iter().any(|processor| processor.publish_source)
+}
+
+pub fn expand(template: &str, asset: &Utf8Path) -> String {
+    let input_dir = asset
+        .parent()
+        .map(|dir| dir.as_str())
+        .filter(|dir| !dir.is_empty())
+        .unwrap_or(".");
+    template
+        .replace("{input}", asset.as_str())
+        .replace("{input_dir}", input_dir)
+        .replace("{stem}", asset.file_stem().unwrap_or_default())
+        .replace("{name}", asset.file_name().unwrap_or_default())
+}
+
+pub fn outputs(processor: &AssetProcessor, asset: &Utf8Path) -> Vec<Utf8PathBuf> {
+    processor
+        .outputs
+        .iter()
+        .map(|output| Utf8PathBuf::from(expand(output, asset)))
+        .collect()
+}
+
+/// Runs the processor's command for an asset, and hashes the outputs it should have generated
+pub async fn run(
+    processor: &AssetProcessor,
+    asset: &Utf8Path,
+    hash_cache: &HashCache,
+) -> anyhow::Result<Vec<HashedFileRecord>> {
+    let Some((program, args)) = processor.command.split_first() else {
+        bail!("Asset processor for {asset} has no command");
+    };
+    let program = expand(program, asset);
+    let args = args
+        .iter()
+        .map(|arg| expand(arg, asset))
+        .collect::<Vec<_>>();
+
+    info!("Processing {asset} with {program} {args:?}");
+    let output = Command::new(&program)
+        .args(&args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| anyhow!("Couldn't run {program} to process {asset} - {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "Processing {asset} with {program} failed ({}) - {}",
+            output.status,
+            stderr.trim()
+        );
+    }
+
+    let cwd = Utf8PathBuf::try_from(env::current_dir()?)?;
+    let records = outputs(processor, asset)
+        .into_iter()
+        .map(|relative_path| {
+            let local_path = cwd.join(&relative_path);
+            let Some(name) = relative_path.file_name().map(ToString::to_string) else {
+                bail!("Asset processor output {relative_path} isn't a file");
+            };
+            let hash = hash_cache
+                .hash_file(&local_path)
+                .map_err(|e| anyhow!("Processing {asset} didn't produce {relative_path} - {e}"))?;
+            Ok(HashedFileRecord::new(relative_path, local_path, name, hash))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if let Err(e) = hash_cache.persist() {
+        debug!("Couldn't persist hash cache - {e}");
+    }
+    Ok(records)
+}
+
+/// Reports a processor failure like a compiler error, so it reaches runners along with the build's other diagnostics
+pub fn failure_diagnostic(asset: &Utf8Path, error: &anyhow::Error) -> BuildDiagnostic {
+    BuildDiagnostic {
+        level: DiagnosticLevel::Error,
+        message: format!("Couldn't process asset {asset} - {error}"),
+        code: None,
+        spans: vec![],
+        children: vec![],
+        rendered: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_temp_dir::test_temp_dir;
+
+    fn processor(command: &[&str], outputs: &[&str]) -> AssetProcessor {
+        AssetProcessor {
+            globs: vec!["**/*.wgsl".to_string()],
+            command: command.iter().map(ToString::to_string).collect(),
+            outputs: outputs.iter().map(ToString::to_string).collect(),
+            publish_source: false,
+        }
+    }
+
+    #[test]
+    fn processors_match_assets_by_glob() {
+        let processors =
+            AssetProcessors::new(&[processor(&["true"], &[])]).expect("Couldn't create processors");
+
+        assert_eq!(
+            processors
+                .matching(Utf8Path::new("assets/shaders/light.wgsl"))
+                .len(),
+            1
+        );
+        assert!(processors
+            .matching(Utf8Path::new("assets/light.png"))
+            .is_empty());
+        assert!(publishes_source(&[]));
+        assert!(!publishes_source(&[processor(&["true"], &[])]));
+    }
+
+    #[test]
+    fn templates_expand_from_the_asset_path() {
+        let asset = Utf8Path::new("assets/shaders/light.wgsl");
+
+        assert_eq!(expand("{input}", asset), "assets/shaders/light.wgsl");
+        assert_eq!(
+            expand("{input_dir}/{stem}.spv", asset),
+            "assets/shaders/light.spv"
+        );
+        assert_eq!(expand("{name}", asset), "light.wgsl");
+        assert_eq!(
+            expand("{input_dir}/{stem}.spv", Utf8Path::new("light.wgsl")),
+            "./light.spv"
+        );
+    }
+
+    #[tokio::test]
+    async fn running_a_processor_publishes_its_outputs() {
+        let dir = test_temp_dir!();
+        let root = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        let asset = root.join("light.wgsl");
+        std::fs::write(&asset, "shader").unwrap();
+
+        let records = run(
+            &processor(
+                &["cp", "{input}", "{input_dir}/{stem}.spv"],
+                &["{input_dir}/{stem}.spv"],
+            ),
+            &asset,
+            &HashCache::load(root.join("hashes.json")),
+        )
+        .await
+        .expect("Processor failed");
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].name, "light.spv");
+        assert_eq!(records[0].local_path, root.join("light.spv"));
+        assert_eq!(records[0].hash, *blake3::hash(b"shader").as_bytes());
+    }
+
+    #[tokio::test]
+    async fn failing_processors_report_an_error() {
+        let dir = test_temp_dir!();
+        let root = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        let asset = root.join("light.wgsl");
+        std::fs::write(&asset, "shader").unwrap();
+
+        let hash_cache = HashCache::load(root.join("hashes.json"));
+
+        let failed = run(&processor(&["false"], &[]), &asset, &hash_cache).await;
+        assert!(failed.is_err());
+
+        let missing_output = run(
+            &processor(&["true"], &["{input_dir}/{stem}.spv"]),
+            &asset,
+            &hash_cache,
+        )
+        .await;
+        let diagnostic = failure_diagnostic(&asset, &missing_output.unwrap_err());
+        assert!(diagnostic.level.is_error());
+        assert!(diagnostic.message.contains("light.wgsl"));
+    }
+}
@@ -1,4 +1,3 @@
-use cargo_metadata::Metadata;
 use debounced::debounced;
 use futures_util::future::join_all;
 use serde::{Deserialize, Serialize};
@@ -10,26 +9,37 @@ use std::{
         atomic::{AtomicBool, AtomicU32, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tokio_stream::{wrappers::UnboundedReceiverStream, StreamExt};
 
 use anyhow::bail;
 
 use camino::{Utf8Path, Utf8PathBuf};
-use dexterous_developer_types::{cargo_path_utils::dylib_path, Target, TargetBuildSettings};
+use dexterous_developer_types::{
+    cargo_path_utils::dylib_path, BuildDiagnostic, BuildTimings, BuilderTypes, DiagnosticLevel,
+    DiagnosticSpan, InFlightBuildPolicy, Linker, Target, TargetBuildSettings,
+};
+use thiserror::Error;
 use tokio::{
     io::{AsyncBufReadExt, BufReader},
-    process::Command,
     select,
-    sync::Mutex,
+    sync::{oneshot, Mutex},
     task::JoinHandle,
 };
 use tracing::{debug, error, info, trace};
 
+use super::metadata::CachedMetadata;
+use super::retention::{
+    collect_unreferenced, delete_artifact, BuildArtifact, DEFAULT_RETAINED_BUILDS,
+};
+use super::rust_flags::{prepared_command_rustflags, user_rustflags};
+use super::toolchain::{is_other_toolchain, toolchain_library_directories};
+use crate::asset_processor::{self, AssetProcessors};
+use crate::hash_cache::HashCache;
 use crate::types::{
-    BuildOutputMessages, Builder, BuilderIncomingMessages, BuilderInitializer,
-    BuilderOutgoingMessages, HashedFileRecord,
+    BuildOutputMessages, BuildPermit, BuildQueue, Builder, BuilderIncomingMessages,
+    BuilderInitializer, BuilderOutgoingMessages, HashedFileRecord, QueuedBuild,
 };
 
 pub struct DefaultBuilderInitializer {
@@ -59,113 +69,96 @@ pub struct DefaultBuilder {
     settings: TargetBuildSettings,
     outgoing: tokio::sync::broadcast::Sender<BuilderOutgoingMessages>,
     output: tokio::sync::broadcast::Sender<BuildOutputMessages>,
+    build_queue: Arc<std::sync::Mutex<Option<Arc<dyn BuildQueue>>>>,
     #[allow(dead_code)]
     handle: tokio::task::JoinHandle<()>,
 }
 
 async fn build(
     target: Target,
-    TargetBuildSettings {
-        working_dir,
-        package_or_example,
-        features,
-        mut manifest_path,
-        additional_library_directories,
-        apple_sdk_directory,
-        ..
-    }: TargetBuildSettings,
-    previous_versions: Arc<Mutex<Vec<(String, Utf8PathBuf)>>>,
+    settings: TargetBuildSettings,
+    previous_versions: Arc<Mutex<Vec<BuildArtifact>>>,
+    metadata_cache: Arc<Mutex<Option<CachedMetadata>>>,
+    outgoing: tokio::sync::broadcast::Sender<BuilderOutgoingMessages>,
     sender: tokio::sync::broadcast::Sender<BuildOutputMessages>,
     id: u32,
+    mut cancel: oneshot::Receiver<()>,
 ) -> Result<(), anyhow::Error> {
     info!("Default Build {id} Started");
     eprintln!("Starting Builder");
 
-    let (artifact_name, artifact_file_name) = {
-        let mut cmd = Command::new("cargo");
-        cmd.arg("metadata");
-        if let Some(manifest_path) = &manifest_path {
-            cmd.arg("--manifest-path").arg(manifest_path);
-        }
-        if let Some(working_dir) = &working_dir {
-            cmd.current_dir(working_dir);
-        }
+    let profile = settings.profile().to_owned();
+    let profile_directory = settings.profile_directory().to_owned();
 
-        eprintln!("Requesting Cargo Metadata");
-        let output = cmd.output().await?;
+    let TargetBuildSettings {
+        working_dir,
+        package_or_example,
+        features,
+        manifest_path,
+        additional_library_directories,
+        apple_sdk_directory,
+        opt_level,
+        linker,
+        environment,
+        toolchain,
+        builder,
+        reloadable_crates,
+        check_before_build,
+        code_watch_folders,
+        ..
+    } = settings;
 
-        eprintln!("Got Cargo Metadata");
+    let mut timings = BuildTimings::default();
 
-        if !output.status.success() {
-            bail!("Failed to get Cargo metadata");
-        }
-        let output: Metadata = serde_json::from_slice(&output.stdout)?;
-
-        match &package_or_example {
-            dexterous_developer_types::PackageOrExample::DefaulPackage => {
-                let Some(root) = (if let Some(package) = output.root_package() {
-                    find_package_target(package, target, id)
-                } else if output.workspace_default_members.len() == 1 {
-                    let default_member = output.workspace_default_members.first().unwrap();
-                    if let Some(package) = output.packages.iter().find(|p| p.id == *default_member)
-                    {
-                        find_package_target(package, target, id)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }) else {
-                    bail!("Can't find default package target");
-                };
-                root
-            }
-            dexterous_developer_types::PackageOrExample::Package(package) => {
-                let Some(package) = output.packages.iter().find(|p| p.name == *package) else {
-                    let packages = output
-                        .packages
-                        .iter()
-                        .map(|v| v.name.to_string())
-                        .collect::<Vec<_>>();
-                    bail!("Couldn't find package - {package} - {packages:?}");
-                };
-                let Some(p) = find_package_target(package, target, id) else {
-                    bail!("Can't find package target");
-                };
-                p
+    let phase_started = Instant::now();
+    let CachedMetadata {
+        artifact_name,
+        manifest_path,
+        sysroot,
+        ..
+    } = {
+        let mut cached = metadata_cache.lock().await;
+        match cached.as_ref() {
+            Some(metadata) if !metadata.is_stale() => {
+                trace!("Using cached cargo metadata");
+                metadata.clone()
             }
-            dexterous_developer_types::PackageOrExample::Example(e) => {
-                let Some((example_target, package)) = output
-                    .packages
-                    .into_iter()
-                    .flat_map(|e| {
-                        e.targets
-                            .iter()
-                            .map(|t| (t.clone(), e.clone()))
-                            .collect::<Vec<_>>()
-                    })
-                    .find(|(t, _)| t.is_example() && t.name == *e)
-                else {
-                    bail!("No such example");
-                };
-
-                if manifest_path.is_none() {
-                    manifest_path = Some(package.manifest_path);
+            _ => {
+                let metadata = CachedMetadata::load(
+                    working_dir.as_deref(),
+                    manifest_path.as_deref(),
+                    toolchain.as_deref(),
+                    &package_or_example,
+                )
+                .await?;
+                if code_watch_folders.is_empty() {
+                    let _ = outgoing.send(BuilderOutgoingMessages::WatchCodeFolders(
+                        metadata.code_watch_folders.clone(),
+                    ));
                 }
-                let artifact_name = example_target.name.clone();
-                let artifact_file_name = target.dynamic_lib_name(&format!("{artifact_name}.{id}"));
-                (artifact_name, artifact_file_name)
+                cached.replace(metadata.clone());
+                metadata
             }
         }
     };
+    let artifact_file_name = target.dynamic_lib_name(&format!("{artifact_name}.{id}"));
+    timings.metadata = phase_started.elapsed();
     eprintln!("Got Artifact Name and File");
     info!("Artifact Name: {artifact_name} File: {artifact_file_name}");
 
+    let reloadable_crates = reloadable_crates
+        .iter()
+        .map(|crate_name| crate_name.replace('-', "_"))
+        .filter(|crate_name| *crate_name != artifact_name.replace('-', "_"))
+        .collect::<Vec<_>>();
+
     let default_run_settings = if id == 1 {
         DefaultRunParams::InitialRun
     } else {
-        let target_dir = Utf8PathBuf::from(format!("./target/hot-reload/{target}/{target}/debug"))
-            .canonicalize_utf8()?;
+        let target_dir = Utf8PathBuf::from(format!(
+            "./target/hot-reload/{target}/{target}/{profile_directory}"
+        ))
+        .canonicalize_utf8()?;
         let deps = target_dir.join("deps");
         let examples = target_dir.join("examples");
         if !target_dir.exists() {
@@ -184,7 +177,7 @@ async fn build(
                 let previous_versions = previous_versions.lock().await;
                 previous_versions
                     .iter()
-                    .filter_map(|(name, path)| {
+                    .filter_map(|BuildArtifact { name, path, .. }| {
                         if path.exists() {
                             Some(name.clone())
                         } else {
@@ -207,13 +200,23 @@ async fn build(
 
     let target_dir = Utf8PathBuf::from_path_buf(dunce::canonicalize(target_dir)?)
         .map_err(|e| anyhow::anyhow!("Can't convert to utf8 {e:?}"))?;
-    let default_out = target_dir.join(format!("{target}")).join("debug");
+    let check_dir = target_dir
+        .parent()
+        .map(|dir| dir.join("check").join(target.as_str()))
+        .unwrap_or_else(|| target_dir.join("check"));
+    let default_out = target_dir
+        .join(format!("{target}"))
+        .join(&profile_directory);
     let deps = default_out.join("deps");
     let examples = default_out.join("examples");
     let artifact_path = default_out.join(&artifact_file_name);
 
     info!("Paths ready - {artifact_path}");
 
+    // Only libraries from these directories get shipped - anything else found on the search path belongs to the system
+    let mut shippable_directories = additional_library_directories.clone();
+    shippable_directories.push(target_dir.clone());
+
     let mut lib_directories = additional_library_directories.clone();
     lib_directories.push(default_out.clone());
     lib_directories.push(deps.clone());
@@ -236,37 +239,79 @@ async fn build(
         dexterous_developer_types::PackageOrExample::Example(example) => {
             options.example = vec![example.to_owned()];
         }
+        dexterous_developer_types::PackageOrExample::Bin(bin) => {
+            options.bin = vec![bin.to_owned()];
+        }
     }
 
     options.common.features = features;
-    options.message_format = vec!["json-render-diagnostics".to_string()];
-    options.profile = Some("dev".to_string());
+    options.message_format = vec!["json-diagnostic-rendered-ansi".to_string()];
+    options.profile = Some(profile.clone());
     options.target = vec![target.to_string()];
 
+    let check_options = check_before_build.then(|| {
+        let mut check = cargo_options::Check {
+            common: options.common.clone(),
+            manifest_path: options.manifest_path.clone(),
+            ..Default::default()
+        };
+        check.common.target_dir = Some(check_dir.clone().into_std_path_buf());
+        check.check.packages = options.packages.clone();
+        check.check.example = options.example.clone();
+        check.check.bin = options.bin.clone();
+        check
+    });
+
     let rustc = which::which("dexterous_developer_rustc_wrapper")?;
     let Ok(rustc) = Utf8PathBuf::from_path_buf(rustc) else {
         bail!("Couldn't get linker path");
     };
     let rustc = rustc.canonicalize_utf8()?;
 
-    let cargo = options.command();
+    let cargo = match builder {
+        BuilderTypes::Default => options.command(),
+        BuilderTypes::Zig => cargo_zigbuild::Rustc {
+            cargo: options,
+            ..Default::default()
+        }
+        .build_command()?,
+    };
     let mut cargo = tokio::process::Command::from(cargo);
 
+    let flags_directory = match &working_dir {
+        Some(working_dir) => working_dir.clone(),
+        None => Utf8PathBuf::from_path_buf(std::env::current_dir()?)
+            .map_err(|e| anyhow::anyhow!("Can't convert to utf8 {e:?}"))?,
+    };
+    let linker = match (builder, linker) {
+        (BuilderTypes::Zig, Linker::Default) => Linker::System,
+        (_, linker) => linker,
+    };
+    let rust_flags = prepared_command_rustflags(
+        cargo.as_std(),
+        user_rustflags(&flags_directory, target, &environment),
+        &linker,
+        target,
+    );
+    info!("Rust Flags: {rust_flags:?}");
+
     if let Some(working_dir) = working_dir {
         cargo.current_dir(&working_dir);
     }
 
-    let mut rust_flags = "-Cprefer-dynamic".to_owned();
-
-    if matches!(target, Target::Linux | Target::LinuxArm | Target::Windows) {
-        rust_flags = format!("{rust_flags} -Clink-arg=-fuse-ld=lld");
-    }
-
     cargo
+        .envs(&environment)
         .env_remove("LD_DEBUG")
         .env("RUSTC_WORKSPACE_WRAPPER", rustc)
         .env("DEXTEROUS_DEVELOPER_LINKER_TARGET", target.as_str())
         .env("DEXTEROUS_DEVELOPER_PACKAGE_NAME", &artifact_name)
+        .env(
+            "DEXTEROUS_DEVELOPER_CRATE_TYPE",
+            match &package_or_example {
+                dexterous_developer_types::PackageOrExample::Bin(_) => "bin",
+                _ => "",
+            },
+        )
         .env("DEXTEROUS_DEVELOPER_OUTPUT_FILE", &artifact_path)
         .env(
             "DEXTEROUS_DEVELOPER_LIB_DIRECTORES",
@@ -276,61 +321,95 @@ async fn build(
             "DEXTEROUS_DEVELOPER_DEFAULT_RUN",
             serde_json::to_string(&default_run_settings)?,
         )
-        .env("RUSTFLAGS", rust_flags);
-
-    let _ = sender.send(BuildOutputMessages::StartedBuild(id));
-    eprintln!("Started Compilation");
-    info!("Ready to start build");
-
-    let mut child = cargo
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?;
-
-    let mut succeeded = false;
-
-    let mut artifacts = Vec::with_capacity(20);
+        .env(
+            "DEXTEROUS_DEVELOPER_RELOADABLE_CRATES",
+            serde_json::to_string(&reloadable_crates)?,
+        )
+        .env_remove("RUSTFLAGS")
+        .env("CARGO_ENCODED_RUSTFLAGS", rust_flags.join("\x1f"));
 
-    let Some(output) = child.stdout.take() else {
-        bail!("No Std Out");
-    };
+    if let Some(toolchain) = &toolchain {
+        cargo.env("RUSTUP_TOOLCHAIN", toolchain);
+    }
 
-    let Some(error) = child.stderr.take() else {
-        bail!("No Std Err");
-    };
+    if let Some(opt_level) = opt_level {
+        let profile_key = profile.to_uppercase().replace('-', "_");
+        cargo.env(
+            format!("CARGO_PROFILE_{profile_key}_OPT_LEVEL"),
+            opt_level.to_string(),
+        );
+    }
 
-    tokio::spawn(async move {
-        let mut out_reader = BufReader::new(error).lines();
-        while let Ok(Some(line)) = out_reader.next_line().await {
-            println!("Compilation - {line}");
+    // The check runs in its own target dir, so its metadata-only artifacts never invalidate the hot reload build's.
+    let check = check_options.map(|options| {
+        let mut check = tokio::process::Command::from(options.command());
+        if let Some(working_dir) = cargo.as_std().get_current_dir() {
+            check.current_dir(working_dir);
+        }
+        check
+            .envs(&environment)
+            .env_remove("RUSTFLAGS")
+            .env("CARGO_ENCODED_RUSTFLAGS", rust_flags.join("\x1f"));
+        if let Some(toolchain) = &toolchain {
+            check.env("RUSTUP_TOOLCHAIN", toolchain);
         }
+        check
     });
 
-    let mut out_reader = BufReader::new(output).lines();
+    let _ = sender.send(BuildOutputMessages::StartedBuild(id));
+    eprintln!("Started Compilation");
+    info!("Ready to start build");
 
-    while let Some(line) = out_reader.next_line().await? {
-        trace!("Compiler Output: {line}");
-        let message = serde_json::from_str(&line)?;
+    if cancel.try_recv().is_ok() {
+        return Err(BuildCancelled(id).into());
+    }
 
-        match &message {
-            cargo_metadata::Message::CompilerArtifact(artifact) => {
-                artifacts.push(artifact.clone());
-            }
-            cargo_metadata::Message::BuildFinished(finished) => {
-                info!("Build Finished: {finished:?}");
-                succeeded = finished.success;
-            }
-            msg => trace!("Compiler: {msg:?}"),
+    let mut reported_diagnostics = vec![];
+
+    if let Some(mut check) = check {
+        let phase_started = Instant::now();
+        let CargoOutput {
+            succeeded,
+            error_count,
+        } = run_cargo(
+            &mut check,
+            &sender,
+            id,
+            &mut cancel,
+            &mut reported_diagnostics,
+        )
+        .await?;
+        timings.check = phase_started.elapsed();
+        eprintln!("Check Completed");
+
+        if !succeeded {
+            error!("Check Failed");
+            bail!("Failed check - {error_count} errors");
         }
     }
 
+    let phase_started = Instant::now();
+    let CargoOutput {
+        succeeded,
+        error_count,
+    } = run_cargo(
+        &mut cargo,
+        &sender,
+        id,
+        &mut cancel,
+        &mut reported_diagnostics,
+    )
+    .await?;
+
+    timings.compilation = phase_started.elapsed();
     eprintln!("Build Completed");
 
     if !succeeded {
         error!("Build Failed");
-        bail!("Failed to build");
+        bail!("Failed to build - {error_count} errors");
     }
 
+    let phase_started = Instant::now();
     let mut libraries = HashMap::<String, Utf8PathBuf>::with_capacity(20);
     libraries.insert(artifact_file_name.clone(), artifact_path.clone());
 
@@ -351,30 +430,20 @@ async fn build(
 
     path_var.append(&mut dylib_paths);
     path_var.append(&mut root_dirs);
-    path_var.push(
-        Utf8PathBuf::from_path_buf(env::current_dir()?)
-            .unwrap_or_default()
-            .join("target")
-            .join("hot-reload")
-            .join(target.to_string())
-            .join(target.to_string())
-            .join("debug")
-            .join("deps"),
-    );
-
-    {
-        let rustup_home = home::rustup_home()?;
-        let toolchains = rustup_home.join("toolchains");
-
-        let mut dir = tokio::fs::read_dir(toolchains).await?;
-
-        while let Ok(Some(child)) = dir.next_entry().await {
-            if child.file_type().await?.is_dir() {
-                let path = Utf8PathBuf::from_path_buf(child.path()).unwrap_or_default();
-                path_var.push(path.join("lib"));
-            }
-        }
-    }
+    let hot_reload_deps = Utf8PathBuf::from_path_buf(env::current_dir()?)
+        .unwrap_or_default()
+        .join("target")
+        .join("hot-reload")
+        .join(target.to_string())
+        .join(target.to_string())
+        .join(&profile_directory)
+        .join("deps");
+    shippable_directories.push(hot_reload_deps.clone());
+    shippable_directories.push(sysroot.clone());
+    path_var.push(hot_reload_deps);
+
+    path_var.retain(|dir| !is_other_toolchain(dir, &sysroot));
+    path_var.extend(toolchain_library_directories(&sysroot, target));
 
     trace!("Path Var for DyLib Search: {path_var:?}");
 
@@ -422,6 +491,7 @@ async fn build(
     for (name, library) in initial_libraries.iter() {
         process_dependencies_recursive(
             &searchable_files,
+            &shippable_directories,
             &mut libraries,
             &mut dependencies,
             name,
@@ -429,18 +499,21 @@ async fn build(
         )?;
     }
 
+    timings.dependency_scan = phase_started.elapsed();
+
+    let phase_started = Instant::now();
+    let hash_cache = HashCache::shared();
     let libraries = {
         libraries
             .iter()
             .map(|(library, local_path)| {
-                let file = std::fs::read(local_path)?;
-                let hash = blake3::hash(&file);
+                let hash = hash_cache.hash_file(local_path)?;
 
                 Ok(HashedFileRecord {
                     name: library.clone(),
                     local_path: local_path.clone(),
                     relative_path: Utf8PathBuf::from(format!("./{library}")),
-                    hash: hash.as_bytes().to_owned(),
+                    hash,
                     dependencies: dependencies
                         .get(library.as_str())
                         .cloned()
@@ -449,10 +522,25 @@ async fn build(
             })
             .collect::<anyhow::Result<Vec<_>>>()?
     };
+    if let Err(e) = hash_cache.persist() {
+        debug!("Couldn't persist hash cache - {e}");
+    }
+    timings.hashing = phase_started.elapsed();
 
     {
         let mut previous = previous_versions.lock().await;
-        previous.push((format!("{artifact_name}.{id}"), artifact_path.clone()));
+        previous.push(BuildArtifact {
+            id,
+            name: format!("{artifact_name}.{id}"),
+            path: artifact_path.clone(),
+        });
+        for crate_name in reloadable_crates.iter() {
+            let name = format!("{crate_name}.{id}");
+            let path = artifact_path.with_file_name(target.dynamic_lib_name(&name));
+            if path.exists() {
+                previous.push(BuildArtifact { id, name, path });
+            }
+        }
     }
 
     let _ = sender.send(BuildOutputMessages::EndedBuild {
@@ -460,48 +548,293 @@ async fn build(
         libraries,
         root_library: artifact_file_name,
     });
-    info!("Build {id} Completed");
+
+    info!("Build {id} Completed - {timings}");
+    let _ = sender.send(BuildOutputMessages::BuildTimings { id, timings });
     Ok(())
 }
 
-fn find_package_target(
-    package: &cargo_metadata::Package,
-    target: Target,
+#[derive(Error, Debug)]
+#[error("Build {0} was cancelled")]
+struct BuildCancelled(u32);
+
+struct CargoOutput {
+    succeeded: bool,
+    error_count: usize,
+}
+
+/// Runs a cargo command with json message output, forwarding any diagnostics that weren't already reported for this build.
+async fn run_cargo(
+    cargo: &mut tokio::process::Command,
+    sender: &tokio::sync::broadcast::Sender<BuildOutputMessages>,
     id: u32,
-) -> Option<(String, String)> {
-    let targets = &package.targets;
+    cancel: &mut oneshot::Receiver<()>,
+    reported_diagnostics: &mut Vec<BuildDiagnostic>,
+) -> anyhow::Result<CargoOutput> {
+    // Cargo gets its own process group, so cancelling can take rustc and the linker down with it
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(cargo.as_std_mut(), 0);
+    let mut child = cargo
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
 
-    let package_target = if let Some(lib) = targets.iter().find(|target| target.is_lib()) {
-        lib
-    } else if let Some(default_run) = &package.default_run {
-        targets
-            .iter()
-            .find(|target| target.is_bin() && &target.name == default_run)?
-    } else if let Some(first_bin) = targets.iter().find(|target| target.is_bin()) {
-        first_bin
-    } else {
-        return None;
+    let mut succeeded = false;
+    let mut error_count = 0;
+
+    let Some(output) = child.stdout.take() else {
+        bail!("No Std Out");
     };
 
-    let artifact_name = package_target.name.clone();
-    let artifact_file_name = target.dynamic_lib_name(&format!("{artifact_name}.{id}"));
+    let Some(error) = child.stderr.take() else {
+        bail!("No Std Err");
+    };
+
+    tokio::spawn(async move {
+        let mut out_reader = BufReader::new(error).lines();
+        while let Ok(Some(line)) = out_reader.next_line().await {
+            println!("Compilation - {line}");
+        }
+    });
+
+    let mut out_reader = BufReader::new(output).lines();
+
+    loop {
+        let line = select! {
+            line = out_reader.next_line() => line?,
+            Ok(()) = &mut *cancel => {
+                kill_process_tree(&mut child).await?;
+                return Err(BuildCancelled(id).into());
+            }
+        };
+        let Some(line) = line else {
+            break;
+        };
+        trace!("Compiler Output: {line}");
+        let message = serde_json::from_str(&line)?;
+
+        match &message {
+            cargo_metadata::Message::CompilerMessage(message) => {
+                let diagnostic = convert_diagnostic(&message.message);
+                if diagnostic.level.is_error() {
+                    error_count += 1;
+                }
+                if reported_diagnostics.contains(&diagnostic) {
+                    continue;
+                }
+                // The local echo keeps cargo's colours, the forwarded diagnostic doesn't
+                if let Some(rendered) = &message.message.rendered {
+                    eprint!("{rendered}");
+                }
+                reported_diagnostics.push(diagnostic.clone());
+                let _ = sender.send(BuildOutputMessages::CompilerDiagnostic { id, diagnostic });
+            }
+            cargo_metadata::Message::BuildFinished(finished) => {
+                info!("Build Finished: {finished:?}");
+                succeeded = finished.success;
+            }
+            msg => trace!("Compiler: {msg:?}"),
+        }
+    }
+
+    Ok(CargoOutput {
+        succeeded,
+        error_count,
+    })
+}
+
+/// Kills cargo along with everything it spawned, so nothing keeps writing into the target directory the next build uses
+async fn kill_process_tree(child: &mut tokio::process::Child) -> std::io::Result<()> {
+    let Some(pid) = child.id() else {
+        return Ok(());
+    };
+
+    #[cfg(unix)]
+    {
+        // SAFETY: killpg only sends a signal - the group was created for cargo by process_group(0), so it only holds cargo and its descendants
+        let result = unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) };
+        if result != 0 {
+            debug!(
+                "Couldn't kill process group {pid} - {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    #[cfg(windows)]
+    {
+        let _ = tokio::process::Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await;
+    }
 
-    Some((artifact_name, artifact_file_name))
+    child.kill().await
+}
+
+fn convert_diagnostic(diagnostic: &cargo_metadata::diagnostic::Diagnostic) -> BuildDiagnostic {
+    use cargo_metadata::diagnostic::DiagnosticLevel as Level;
+
+    BuildDiagnostic {
+        level: match diagnostic.level {
+            Level::Ice => DiagnosticLevel::InternalCompilerError,
+            Level::Error => DiagnosticLevel::Error,
+            Level::Warning => DiagnosticLevel::Warning,
+            Level::FailureNote => DiagnosticLevel::FailureNote,
+            Level::Note => DiagnosticLevel::Note,
+            Level::Help => DiagnosticLevel::Help,
+            _ => DiagnosticLevel::Unknown,
+        },
+        message: diagnostic.message.clone(),
+        code: diagnostic.code.as_ref().map(|code| code.code.clone()),
+        spans: diagnostic
+            .spans
+            .iter()
+            .map(|span| DiagnosticSpan {
+                file_name: Utf8PathBuf::from(&span.file_name),
+                line_start: span.line_start,
+                line_end: span.line_end,
+                column_start: span.column_start,
+                column_end: span.column_end,
+                is_primary: span.is_primary,
+                label: span.label.clone(),
+            })
+            .collect(),
+        children: diagnostic.children.iter().map(convert_diagnostic).collect(),
+        rendered: diagnostic.rendered.as_deref().map(strip_ansi),
+    }
+}
+
+/// Removes terminal escape sequences (colours, hyperlinks) from rendered compiler output
+fn strip_ansi(rendered: &str) -> String {
+    let mut stripped = String::with_capacity(rendered.len());
+    let mut chars = rendered.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI - parameters, then a final byte in @..=~
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC - terminated by BEL or ST
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\u{7}' {
+                        break;
+                    }
+                    if c == '\u{1b}' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    stripped
 }
 
 fn process_dependencies_recursive(
     searchable_files: &HashMap<String, Utf8PathBuf>,
+    shippable_directories: &[Utf8PathBuf],
+    libraries: &mut HashMap<String, Utf8PathBuf>,
+    dependencies: &mut HashMap<String, Vec<String>>,
+    current_library_name: &str,
+    current_library: &Utf8Path,
+) -> Result<(), anyhow::Error> {
+    let mut stack = vec![];
+    resolve_dependency_graph(
+        &library_imports,
+        searchable_files,
+        shippable_directories,
+        libraries,
+        dependencies,
+        &mut stack,
+        current_library_name,
+        current_library,
+    )
+}
+
+fn resolve_dependency_graph(
+    imports: &impl Fn(&Utf8Path) -> anyhow::Result<Vec<String>>,
+    searchable_files: &HashMap<String, Utf8PathBuf>,
+    shippable_directories: &[Utf8PathBuf],
     libraries: &mut HashMap<String, Utf8PathBuf>,
     dependencies: &mut HashMap<String, Vec<String>>,
+    stack: &mut Vec<String>,
     current_library_name: &str,
     current_library: &Utf8Path,
 ) -> Result<(), anyhow::Error> {
-    eprintln!("Checking current library {current_library_name} {current_library}");
-    let file = fs::read(current_library)?;
+    if dependencies.contains_key(current_library_name) {
+        return Ok(());
+    }
+    trace!("Checking current library {current_library_name} {current_library}");
+
+    let dependency_vec = imports(current_library)?;
+    trace!("Dependencies of {current_library_name}: {dependency_vec:?}");
+
+    dependencies.insert(current_library_name.to_string(), dependency_vec.clone());
+    stack.push(current_library_name.to_string());
+
+    for library_name in dependency_vec.iter() {
+        if library_name.is_empty() {
+            continue;
+        }
+        if let Some(position) = stack.iter().position(|name| name == library_name) {
+            debug!(
+                "Dependency cycle detected: {} -> {library_name}",
+                stack[position..].join(" -> ")
+            );
+            continue;
+        }
+        let library_path = match libraries.get(library_name) {
+            Some(path) => path.clone(),
+            None => {
+                let Some(library_path) = searchable_files.get(library_name) else {
+                    debug!("Couldn't find library with name {library_name}");
+                    continue;
+                };
+                if !shippable_directories
+                    .iter()
+                    .any(|dir| library_path.starts_with(dir))
+                {
+                    debug!("Not shipping system library {library_path}");
+                    continue;
+                }
+                libraries.insert(library_name.to_string(), library_path.clone());
+                library_path.clone()
+            }
+        };
+        resolve_dependency_graph(
+            imports,
+            searchable_files,
+            shippable_directories,
+            libraries,
+            dependencies,
+            stack,
+            library_name,
+            &library_path,
+        )?;
+    }
+
+    stack.pop();
+    Ok(())
+}
+
+fn library_imports(library: &Utf8Path) -> anyhow::Result<Vec<String>> {
+    let file = fs::read(library)?;
     let file = goblin::Object::parse(&file)?;
-    eprintln!("Parsed current library");
 
-    let dependency_vec = match file {
+    let imports = match file {
         goblin::Object::Elf(elf) => {
             let str_table = elf.dynstrtab;
             elf.dynamic
@@ -522,40 +855,33 @@ fn process_dependencies_recursive(
         goblin::Object::Mach(mach) => match mach {
             goblin::mach::Mach::Fat(fat) => {
                 let mut vec = HashSet::new();
-                while let Some(Ok(goblin::mach::SingleArch::MachO(arch))) = fat.into_iter().next() {
-                    let imports = arch.imports()?;
-                    let inner = imports.iter().map(|v| v.dylib.to_string());
-                    vec.extend(inner);
+                for arch in fat.into_iter() {
+                    if let Ok(goblin::mach::SingleArch::MachO(arch)) = arch {
+                        let imports = arch.imports()?;
+                        vec.extend(imports.iter().map(|v| mach_library_name(v.dylib)));
+                    }
                 }
                 vec
             }
-            goblin::mach::Mach::Binary(std) => {
-                std.imports()?.iter().map(|v| v.dylib.to_string()).collect()
-            }
+            goblin::mach::Mach::Binary(std) => std
+                .imports()?
+                .iter()
+                .map(|v| mach_library_name(v.dylib))
+                .collect(),
         },
         _ => HashSet::default(),
     };
 
-    eprintln!("Dependencies: {dependency_vec:?}");
+    let mut imports = imports.into_iter().collect::<Vec<_>>();
+    imports.sort();
+    Ok(imports)
+}
 
-    for library_name in dependency_vec.iter() {
-        if library_name.is_empty() {
-            continue;
-        }
-        if libraries.contains_key(library_name) {
-            continue;
-        }
-        let Some(library_path) = searchable_files.get(library_name) else {
-            debug!("Couldn't find library with name {library_name}");
-            continue;
-        };
-        libraries.insert(library_name.to_string(), library_path.clone());
-    }
-    dependencies.insert(
-        current_library_name.to_string(),
-        dependency_vec.into_iter().collect(),
-    );
-    Ok(())
+fn mach_library_name(dylib: &str) -> String {
+    Utf8Path::new(dylib)
+        .file_name()
+        .unwrap_or(dylib)
+        .to_string()
 }
 
 impl DefaultBuilder {
@@ -570,9 +896,15 @@ impl DefaultBuilder {
         let id = Arc::new(AtomicU32::new(1));
         let build_active = Arc::new(AtomicBool::new(false));
         let build_pending = Arc::new(AtomicBool::new(false));
+        let cancel_build = Arc::new(std::sync::Mutex::new(None));
         let previous_versions = Arc::new(Mutex::new(vec![]));
+        let metadata_cache = Arc::new(Mutex::new(None));
+        let build_queue: Arc<std::sync::Mutex<Option<Arc<dyn BuildQueue>>>> = Default::default();
+        let asset_processors = AssetProcessors::new(&settings.asset_processors)?;
+        let processing_assets = Arc::new(Mutex::new(()));
 
         let handle = {
+            let build_queue = build_queue.clone();
             let outgoing_tx = outgoing_tx.clone();
             let output_tx = output_tx.clone();
             let settings = settings.clone();
@@ -587,20 +919,33 @@ impl DefaultBuilder {
 
                 let mut debounced = debounced(stream, delay);
                 let first_build_triggered = Arc::new(AtomicBool::new(false));
+                let mut paused = false;
+                let mut changed_while_paused = false;
+                let mut pending_changes: Vec<Utf8PathBuf> = vec![];
 
                 loop {
                     select! {
                         Some(()) = debounced.next() => {
-                            if first_build_triggered.load(Ordering::SeqCst) {
+                            if paused {
+                                trace!("Builds for {target} are paused");
+                                changed_while_paused = true;
+                            } else if first_build_triggered.load(Ordering::SeqCst) {
+                                if !pending_changes.is_empty() {
+                                    info!("Rebuilding {target} after changes to {pending_changes:?}");
+                                    pending_changes.clear();
+                                }
                                 trigger_build(
                                     &build_active,
                                     &build_pending,
+                                    &cancel_build,
                                     &id,
                                     &outgoing_tx,
                                     target,
                                     &settings,
                                     &output_tx,
                                     &previous_versions,
+                                    &metadata_cache,
+                                    &build_queue,
                                 );
                             } else {
                                 info!("Not building {target} yet");
@@ -611,17 +956,75 @@ impl DefaultBuilder {
                                 BuilderIncomingMessages::RequestBuild(request) => {
                                     if target == request {
                                         info!("Build Request");
+                                        paused = false;
                                         first_build_triggered.store(true, Ordering::SeqCst);
                                         let _ = build_trigger.send(());
                                     }
                                 }
-                                BuilderIncomingMessages::CodeChanged => {
-                                    info!("Code Changed");
+                                BuilderIncomingMessages::CodeChanged(paths) => {
+                                    info!("Code Changed - {paths:?}");
+                                    for path in paths {
+                                        if !pending_changes.contains(&path) {
+                                            pending_changes.push(path);
+                                        }
+                                    }
                                     let _ = build_trigger.send(());
                                 }
+                                BuilderIncomingMessages::ManifestChanged => {
+                                    info!("Manifest Changed");
+                                    metadata_cache.lock().await.take();
+                                }
                                 BuilderIncomingMessages::AssetChanged(asset) => {
                                     trace!("Builder Received Asset Change - {asset:?}");
-                                    let _ = output_tx.send(BuildOutputMessages::AssetUpdated(asset));
+                                    asset_changed(asset, &asset_processors, &processing_assets, &id, &output_tx);
+                                }
+                                BuilderIncomingMessages::AssetRemoved(path) => {
+                                    trace!("Builder Received Asset Removal - {path}");
+                                    asset_removed(path, &asset_processors, &output_tx);
+                                }
+                                BuilderIncomingMessages::AssetRenamed { from, to } => {
+                                    trace!("Builder Received Asset Rename - {from} to {to:?}");
+                                    if asset_processors.matching(&from).is_empty()
+                                        && asset_processors.matching(&to.relative_path).is_empty()
+                                    {
+                                        let _ = output_tx.send(BuildOutputMessages::AssetRenamed { from, to });
+                                    } else {
+                                        asset_removed(from, &asset_processors, &output_tx);
+                                        asset_changed(to, &asset_processors, &processing_assets, &id, &output_tx);
+                                    }
+                                }
+                                BuilderIncomingMessages::PauseBuilds(request) => {
+                                    if target == request {
+                                        info!("Pausing builds for {target}");
+                                        paused = true;
+                                    }
+                                }
+                                BuilderIncomingMessages::ResumeBuilds(request) => {
+                                    if target == request && paused {
+                                        info!("Resuming builds for {target}");
+                                        paused = false;
+                                        if std::mem::take(&mut changed_while_paused) {
+                                            let _ = build_trigger.send(());
+                                        }
+                                    }
+                                }
+                                BuilderIncomingMessages::ReferencedBuilds { target: request, builds } => {
+                                    if target == request {
+                                        let retained = settings.retained_builds.unwrap_or(DEFAULT_RETAINED_BUILDS);
+                                        let removed = {
+                                            let mut previous = previous_versions.lock().await;
+                                            collect_unreferenced(&mut previous, &builds, retained)
+                                        };
+                                        if !removed.is_empty() {
+                                            tokio::spawn(async move {
+                                                for artifact in removed.iter() {
+                                                    if let Err(e) = delete_artifact(artifact).await {
+                                                        error!("Couldn't remove build {} - {e}", artifact.id);
+                                                    }
+                                                }
+                                            });
+                                        }
+                                    }
                                 }
                             }
                         }
@@ -636,77 +1039,191 @@ impl DefaultBuilder {
             target,
             outgoing: outgoing_tx,
             output: output_tx,
+            build_queue,
             handle,
         })
     }
 }
 
+fn asset_changed(
+    asset: HashedFileRecord,
+    asset_processors: &AssetProcessors,
+    processing_assets: &Arc<Mutex<()>>,
+    id: &Arc<AtomicU32>,
+    output_tx: &tokio::sync::broadcast::Sender<BuildOutputMessages>,
+) {
+    let processors = asset_processors.matching(&asset.relative_path);
+    if processors.is_empty() {
+        let _ = output_tx.send(BuildOutputMessages::AssetUpdated(asset));
+        return;
+    }
+    if asset_processor::publishes_source(&processors) {
+        let _ = output_tx.send(BuildOutputMessages::AssetUpdated(asset.clone()));
+    }
+
+    let processing_assets = processing_assets.clone();
+    let id = id.clone();
+    let output_tx = output_tx.clone();
+    tokio::spawn(async move {
+        // Processors run one at a time, so repeated changes to an asset don't race on its outputs
+        let _processing = processing_assets.lock().await;
+        let hash_cache = HashCache::shared();
+        for processor in processors.iter() {
+            match asset_processor::run(processor, &asset.relative_path, &hash_cache).await {
+                Ok(outputs) => {
+                    for output in outputs {
+                        let _ = output_tx.send(BuildOutputMessages::AssetUpdated(output));
+                    }
+                }
+                Err(e) => {
+                    error!("Asset Processing Error - {e}");
+                    // The counter holds the next build's id, so the failure lands with the most recently started build
+                    let _ = output_tx.send(BuildOutputMessages::CompilerDiagnostic {
+                        id: id.load(Ordering::SeqCst).saturating_sub(1),
+                        diagnostic: asset_processor::failure_diagnostic(&asset.relative_path, &e),
+                    });
+                }
+            }
+        }
+    });
+}
+
+fn asset_removed(
+    path: Utf8PathBuf,
+    asset_processors: &AssetProcessors,
+    output_tx: &tokio::sync::broadcast::Sender<BuildOutputMessages>,
+) {
+    for processor in asset_processors.matching(&path).iter() {
+        for output in asset_processor::outputs(processor, &path) {
+            let _ = output_tx.send(BuildOutputMessages::AssetRemoved(output));
+        }
+    }
+    let _ = output_tx.send(BuildOutputMessages::AssetRemoved(path));
+}
+
 fn trigger_build(
     build_active: &Arc<AtomicBool>,
     build_pending: &Arc<AtomicBool>,
+    cancel_build: &Arc<std::sync::Mutex<Option<oneshot::Sender<()>>>>,
     id: &Arc<AtomicU32>,
     outgoing_tx: &tokio::sync::broadcast::Sender<BuilderOutgoingMessages>,
     target: Target,
     settings: &TargetBuildSettings,
     output_tx: &tokio::sync::broadcast::Sender<BuildOutputMessages>,
-    previous_versions: &Arc<Mutex<Vec<(String, Utf8PathBuf)>>>,
+    previous_versions: &Arc<Mutex<Vec<BuildArtifact>>>,
+    metadata_cache: &Arc<Mutex<Option<CachedMetadata>>>,
+    build_queue: &Arc<std::sync::Mutex<Option<Arc<dyn BuildQueue>>>>,
 ) {
     trace!("Triggering Build");
     let previous = build_active.swap(true, std::sync::atomic::Ordering::SeqCst);
     if previous {
         build_pending.store(true, std::sync::atomic::Ordering::SeqCst);
+        if settings.in_flight_build == InFlightBuildPolicy::Restart {
+            let cancel = cancel_build
+                .lock()
+                .ok()
+                .and_then(|mut cancel| cancel.take());
+            if let Some(cancel) = cancel {
+                info!("Restarting in-flight build for {target}");
+                let _ = cancel.send(());
+            }
+        }
     } else {
-        let id = id.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let id = id.clone();
         let _ = outgoing_tx.send(BuilderOutgoingMessages::BuildStarted);
         let output_tx = output_tx.clone();
         let settings = settings.clone();
         let build_pending = build_pending.clone();
         let build_active = build_active.clone();
+        let cancel_build = cancel_build.clone();
         let previous_versions = previous_versions.clone();
+        let metadata_cache = metadata_cache.clone();
+        let build_queue = build_queue.clone();
+        let outgoing_tx = outgoing_tx.clone();
         #[allow(clippy::let_underscore_future)]
         let _: JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
-            build(
-                target,
-                settings.clone(),
-                previous_versions.clone(),
-                output_tx.clone(),
-                id,
-            )
-            .await
-            .map_err(|e| {
-                error!("Build Error - {id} {target} - {e}");
-                let _ = output_tx.send(BuildOutputMessages::FailedBuild(e.to_string()));
-                build_active.swap(false, std::sync::atomic::Ordering::SeqCst);
-                e
-            })?;
-
             loop {
-                let pending = build_pending.swap(false, std::sync::atomic::Ordering::SeqCst);
-                if pending {
-                    build(
-                        target,
-                        settings.clone(),
-                        previous_versions.clone(),
-                        output_tx.clone(),
-                        id,
-                    )
-                    .await
-                    .map_err(|e| {
+                let id = id.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let (cancel_tx, cancel_rx) = oneshot::channel();
+                if let Ok(mut cancel) = cancel_build.lock() {
+                    cancel.replace(cancel_tx);
+                }
+
+                let queue = build_queue.lock().ok().and_then(|queue| queue.clone());
+                let _permit = match queue {
+                    Some(queue) => wait_for_permit(queue.as_ref(), target, id, &output_tx).await,
+                    None => None,
+                };
+
+                let result = build(
+                    target,
+                    settings.clone(),
+                    previous_versions.clone(),
+                    metadata_cache.clone(),
+                    outgoing_tx.clone(),
+                    output_tx.clone(),
+                    id,
+                    cancel_rx,
+                )
+                .await;
+
+                match result {
+                    Ok(()) => {}
+                    Err(e) if e.is::<BuildCancelled>() => {
+                        info!("{e}");
+                        let _ = output_tx.send(BuildOutputMessages::CancelledBuild(id));
+                    }
+                    Err(e) => {
                         error!("Build Error - {id} {target} - {e}");
                         let _ = output_tx.send(BuildOutputMessages::FailedBuild(e.to_string()));
+                        if let Ok(mut cancel) = cancel_build.lock() {
+                            cancel.take();
+                        }
                         build_active.swap(false, std::sync::atomic::Ordering::SeqCst);
-                        e
-                    })?;
-                } else {
+                        return Err(e);
+                    }
+                }
+
+                let pending = build_pending.swap(false, std::sync::atomic::Ordering::SeqCst);
+                if !pending {
                     break;
                 }
             }
+            if let Ok(mut cancel) = cancel_build.lock() {
+                cancel.take();
+            }
             build_active.swap(false, std::sync::atomic::Ordering::SeqCst);
             Ok(())
         });
     }
 }
 
+async fn wait_for_permit(
+    queue: &dyn BuildQueue,
+    target: Target,
+    id: u32,
+    output_tx: &tokio::sync::broadcast::Sender<BuildOutputMessages>,
+) -> Option<BuildPermit> {
+    let QueuedBuild {
+        mut position,
+        mut permit,
+    } = queue.enqueue(target);
+    loop {
+        let current = *position.borrow_and_update();
+        if current > 0 {
+            trace!("Build {id} for {target} queued at {current}");
+            let _ = output_tx.send(BuildOutputMessages::BuildQueued {
+                id,
+                position: current,
+            });
+        }
+        select! {
+            permit = &mut permit => return permit.ok(),
+            Ok(()) = position.changed() => {}
+        }
+    }
+}
+
 impl Builder for DefaultBuilder {
     fn target(&self) -> Target {
         self.target
@@ -725,6 +1242,7 @@ impl Builder for DefaultBuilder {
         None
     }
 
+    /// When no folders are configured they're derived from cargo metadata once it's loaded, see [`BuilderOutgoingMessages::WatchCodeFolders`]
     fn get_code_subscriptions(&self) -> Vec<camino::Utf8PathBuf> {
         self.settings.code_watch_folders.clone()
     }
@@ -733,8 +1251,18 @@ impl Builder for DefaultBuilder {
         self.settings.asset_folders.clone()
     }
 
+    fn runtime_environment(&self) -> HashMap<String, String> {
+        self.settings.runtime_environment.clone()
+    }
+
     fn builder_type(&self) -> dexterous_developer_types::BuilderTypes {
-        dexterous_developer_types::BuilderTypes::Default
+        self.settings.builder
+    }
+
+    fn use_build_queue(&self, queue: Arc<dyn BuildQueue>) {
+        if let Ok(mut build_queue) = self.build_queue.lock() {
+            build_queue.replace(queue);
+        }
     }
 }
 
@@ -759,6 +1287,14 @@ mod test {
     use tokio::process::Command;
     use tokio::time::timeout;
 
+    #[test]
+    fn forwarded_diagnostics_have_no_escape_sequences() {
+        let rendered = "\u{1b}[0m\u{1b}[1m\u{1b}[38;5;9merror[E0425]\u{1b}[0m: \u{1b}]8;;https://doc.rust-lang.org\u{7}cannot find value\u{1b}]8;;\u{1b}\\\n";
+
+        assert_eq!(strip_ansi(rendered), "error[E0425]: cannot find value\n");
+        assert_eq!(strip_ansi("plain"), "plain");
+    }
+
     #[tokio::test]
     async fn can_build_a_package() {
         let dir = test_temp_dir!();
@@ -864,8 +1400,14 @@ mod test {
                         break;
                     }
                     BuildOutputMessages::AssetUpdated(_) => {}
+                    BuildOutputMessages::AssetRemoved(_) => {}
+                    BuildOutputMessages::AssetRenamed { .. } => {}
+                    BuildOutputMessages::CompilerDiagnostic { .. } => {}
                     BuildOutputMessages::KeepAlive => {}
+                    BuildOutputMessages::BuildTimings { .. } => {}
+                    BuildOutputMessages::BuildQueued { .. } => {}
                     BuildOutputMessages::FailedBuild(e) => bail!("Failed Build - {e}"),
+                    BuildOutputMessages::CancelledBuild(id) => bail!("Cancelled Build - {id}"),
                 }
             }
             Ok(())
@@ -883,4 +1425,280 @@ mod test {
         assert!(root_lib_confirmed);
         assert!(library_update_received);
     }
+
+    #[tokio::test]
+    async fn check_phase_fails_fast_with_diagnostics() {
+        let dir = test_temp_dir!();
+        let dir_path = dir.as_path_untracked().to_path_buf();
+
+        let _ = Command::new("cargo")
+            .current_dir(&dir_path)
+            .arg("init")
+            .arg("--lib")
+            .arg("--name=check_lib")
+            .arg("--vcs=none")
+            .output()
+            .await
+            .expect("Failed to create test project");
+        tokio::fs::write(
+            dir_path.join("src").join("lib.rs"),
+            "pub fn broken() -> u32 { \"not a number\" }",
+        )
+        .await
+        .expect("Failed to write broken source");
+
+        let target = Target::current().expect("Couldn't determine current target");
+        let (incoming, _) = tokio::sync::broadcast::channel(100);
+
+        let build = DefaultBuilder::new(
+            target,
+            TargetBuildSettings {
+                package_or_example: PackageOrExample::Package("check_lib".to_string()),
+                working_dir: Utf8PathBuf::from_path_buf(dir_path).ok(),
+                check_before_build: true,
+                ..Default::default()
+            },
+            incoming.clone(),
+        )
+        .expect("Couldn't set up default builder");
+
+        let (_, mut build_messages) = build.outgoing_channel();
+
+        incoming
+            .send(BuilderIncomingMessages::RequestBuild(target))
+            .expect("Failed to request build");
+
+        let mut started = false;
+        let mut errors = 0;
+
+        let result = timeout(Duration::from_secs(100), async {
+            loop {
+                match build_messages.recv().await? {
+                    BuildOutputMessages::StartedBuild(_) => started = true,
+                    BuildOutputMessages::CompilerDiagnostic { diagnostic, .. }
+                        if diagnostic.level.is_error() =>
+                    {
+                        errors += 1;
+                    }
+                    BuildOutputMessages::FailedBuild(e) => return Ok(e),
+                    BuildOutputMessages::EndedBuild { .. } => bail!("Build shouldn't succeed"),
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .expect("Didn't fail on time")
+        .expect("Failed to receive build messages");
+
+        assert!(started);
+        assert!(errors > 0);
+        assert!(result.contains("check"), "Unexpected failure - {result}");
+    }
+
+    #[tokio::test]
+    async fn restarted_builds_report_cancellation_and_stop_their_children() {
+        let dir = test_temp_dir!();
+        let dir_path = dir.as_path_untracked().to_path_buf();
+
+        let _ = Command::new("cargo")
+            .current_dir(&dir_path)
+            .arg("init")
+            .arg("--lib")
+            .arg("--name=restart_lib")
+            .arg("--vcs=none")
+            .output()
+            .await
+            .expect("Failed to create test project");
+        let hold = dir_path.join("hold");
+        let pids = dir_path.join("pids");
+        tokio::fs::write(&hold, "")
+            .await
+            .expect("Failed to write hold file");
+        tokio::fs::write(
+            dir_path.join("build.rs"),
+            format!(
+                r#"use std::io::Write;
+fn main() {{
+    let mut pids = std::fs::OpenOptions::new().create(true).append(true).open({pids:?}).unwrap();
+    writeln!(pids, "{{}}", std::process::id()).unwrap();
+    while std::path::Path::new({hold:?}).exists() {{
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }}
+}}
+"#
+            ),
+        )
+        .await
+        .expect("Failed to write build script");
+
+        let target = Target::current().expect("Couldn't determine current target");
+        let (incoming, _) = tokio::sync::broadcast::channel(100);
+
+        let build = DefaultBuilder::new(
+            target,
+            TargetBuildSettings {
+                package_or_example: PackageOrExample::Package("restart_lib".to_string()),
+                working_dir: Utf8PathBuf::from_path_buf(dir_path).ok(),
+                in_flight_build: InFlightBuildPolicy::Restart,
+                ..Default::default()
+            },
+            incoming.clone(),
+        )
+        .expect("Couldn't set up default builder");
+
+        let (_, mut build_messages) = build.outgoing_channel();
+
+        incoming
+            .send(BuilderIncomingMessages::RequestBuild(target))
+            .expect("Failed to request build");
+
+        let cancelled = timeout(Duration::from_secs(100), async {
+            let mut first = None;
+            loop {
+                match build_messages.recv().await? {
+                    BuildOutputMessages::StartedBuild(id) if first.is_none() => {
+                        first = Some(id);
+                        // Give cargo a chance to get to the build script before restarting
+                        while !tokio::fs::try_exists(&pids).await.unwrap_or(false) {
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                        }
+                        incoming.send(BuilderIncomingMessages::RequestBuild(target))?;
+                    }
+                    BuildOutputMessages::CancelledBuild(id) => return Ok((first, id)),
+                    BuildOutputMessages::EndedBuild { .. } => bail!("Build shouldn't complete"),
+                    BuildOutputMessages::FailedBuild(e) => bail!("Failed Build - {e}"),
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .expect("Wasn't cancelled on time")
+        .expect("Failed to receive build messages");
+
+        assert_eq!(cancelled.0, Some(cancelled.1));
+        #[cfg(target_os = "linux")]
+        {
+            let first_build_script = tokio::fs::read_to_string(&pids)
+                .await
+                .expect("Couldn't read build script pids");
+            let first_build_script = first_build_script.lines().next().unwrap().to_string();
+
+            // The build script may linger briefly as a zombie until it's reaped
+            let stat = Utf8Path::new("/proc")
+                .join(&first_build_script)
+                .join("stat");
+            let mut running = true;
+            for _ in 0..50 {
+                running = std::fs::read_to_string(&stat).is_ok_and(|stat| {
+                    stat.rsplit_once(')')
+                        .is_some_and(|(_, rest)| !rest.trim_start().starts_with('Z'))
+                });
+                if !running {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            assert!(
+                !running,
+                "The cancelled build's build script {first_build_script} is still running"
+            );
+        }
+        let _ = tokio::fs::remove_file(&hold).await;
+    }
+
+    #[test]
+    fn resolves_transitive_dependencies_with_cycles() {
+        let graph: HashMap<&str, Vec<&str>> = [
+            ("root.so", vec!["a.so", "libc.so"]),
+            ("a.so", vec!["b.so"]),
+            ("b.so", vec!["a.so", "c.so"]),
+            ("c.so", vec![]),
+        ]
+        .into_iter()
+        .collect();
+        let imports = |path: &Utf8Path| {
+            Ok(graph[path.file_name().unwrap()]
+                .iter()
+                .map(|name| name.to_string())
+                .collect())
+        };
+        let searchable_files = ["a.so", "b.so", "c.so"]
+            .into_iter()
+            .map(|name| (name.to_string(), Utf8PathBuf::from(format!("/libs/{name}"))))
+            .collect::<HashMap<_, _>>();
+
+        let mut libraries = HashMap::new();
+        let mut dependencies = HashMap::new();
+        resolve_dependency_graph(
+            &imports,
+            &searchable_files,
+            &[Utf8PathBuf::from("/libs")],
+            &mut libraries,
+            &mut dependencies,
+            &mut vec![],
+            "root.so",
+            Utf8Path::new("/out/root.so"),
+        )
+        .expect("Couldn't resolve dependencies");
+
+        let mut shipped = libraries.keys().cloned().collect::<Vec<_>>();
+        shipped.sort();
+        assert_eq!(shipped, vec!["a.so", "b.so", "c.so"]);
+        assert_eq!(dependencies["root.so"], vec!["a.so", "libc.so"]);
+        assert_eq!(dependencies["b.so"], vec!["a.so", "c.so"]);
+        assert!(dependencies["c.so"].is_empty());
+        assert!(!dependencies.contains_key("libc.so"));
+    }
+
+    #[test]
+    fn system_libraries_on_the_search_path_are_not_shipped() {
+        let graph: HashMap<&str, Vec<&str>> = [
+            ("root.dll", vec!["game.dll", "kernel32.dll"]),
+            ("game.dll", vec!["std.dll", "vcruntime140.dll"]),
+            ("std.dll", vec!["kernel32.dll"]),
+            ("kernel32.dll", vec!["ntdll.dll"]),
+            ("vcruntime140.dll", vec![]),
+        ]
+        .into_iter()
+        .collect();
+        let imports = |path: &Utf8Path| {
+            Ok(graph[path.file_name().unwrap()]
+                .iter()
+                .map(|name| name.to_string())
+                .collect())
+        };
+        let searchable_files = [
+            ("game.dll", "/project/target/hot-reload/deps/game.dll"),
+            ("std.dll", "/toolchain/lib/rustlib/std.dll"),
+            ("kernel32.dll", "/windows/system32/kernel32.dll"),
+            ("ntdll.dll", "/windows/system32/ntdll.dll"),
+            ("vcruntime140.dll", "/windows/system32/vcruntime140.dll"),
+        ]
+        .into_iter()
+        .map(|(name, path)| (name.to_string(), Utf8PathBuf::from(path)))
+        .collect::<HashMap<_, _>>();
+
+        let mut libraries = HashMap::new();
+        let mut dependencies = HashMap::new();
+        resolve_dependency_graph(
+            &imports,
+            &searchable_files,
+            &[
+                Utf8PathBuf::from("/project/target/hot-reload"),
+                Utf8PathBuf::from("/toolchain"),
+            ],
+            &mut libraries,
+            &mut dependencies,
+            &mut vec![],
+            "root.dll",
+            Utf8Path::new("/project/target/hot-reload/root.dll"),
+        )
+        .expect("Couldn't resolve dependencies");
+
+        let mut shipped = libraries.keys().cloned().collect::<Vec<_>>();
+        shipped.sort();
+        assert_eq!(shipped, vec!["game.dll", "std.dll"]);
+        assert!(!dependencies.contains_key("kernel32.dll"));
+        assert!(!dependencies.contains_key("ntdll.dll"));
+    }
 }
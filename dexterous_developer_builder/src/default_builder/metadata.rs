@@ -0,0 +1,452 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use anyhow::bail;
+use camino::{Utf8Path, Utf8PathBuf};
+use cargo_metadata::{Metadata, Package};
+use dexterous_developer_types::PackageOrExample;
+use tokio::process::Command;
+use tracing::trace;
+
+use super::toolchain::toolchain_sysroot;
+
+#[derive(Debug, Clone)]
+pub struct CachedMetadata {
+    pub metadata: Arc<Metadata>,
+    pub artifact_name: String,
+    pub manifest_path: Option<Utf8PathBuf>,
+    pub code_watch_folders: Vec<Utf8PathBuf>,
+    /// The sysroot of the toolchain the metadata was loaded with, so rustc isn't asked for it on every build
+    pub sysroot: Utf8PathBuf,
+    manifests: Vec<(Utf8PathBuf, Option<SystemTime>)>,
+}
+
+impl CachedMetadata {
+    pub async fn load(
+        working_dir: Option<&Utf8Path>,
+        manifest_path: Option<&Utf8Path>,
+        toolchain: Option<&str>,
+        package_or_example: &PackageOrExample,
+    ) -> anyhow::Result<Self> {
+        let mut cmd = Command::new("cargo");
+        cmd.arg("metadata");
+        if let Some(manifest_path) = manifest_path {
+            cmd.arg("--manifest-path").arg(manifest_path);
+        }
+        if let Some(working_dir) = working_dir {
+            cmd.current_dir(working_dir);
+        }
+        if let Some(toolchain) = toolchain {
+            cmd.env("RUSTUP_TOOLCHAIN", toolchain);
+        }
+
+        eprintln!("Requesting Cargo Metadata");
+        let output = cmd.output().await?;
+
+        eprintln!("Got Cargo Metadata");
+
+        if !output.status.success() {
+            bail!("Failed to get Cargo metadata");
+        }
+        let metadata: Metadata = serde_json::from_slice(&output.stdout)?;
+
+        let mut manifest_path = manifest_path.map(ToOwned::to_owned);
+
+        let (built_target, root_package) = match package_or_example {
+            PackageOrExample::DefaulPackage => {
+                let package = metadata.root_package().or_else(|| {
+                    if metadata.workspace_default_members.len() == 1 {
+                        let default_member = metadata.workspace_default_members.first()?;
+                        metadata.packages.iter().find(|p| p.id == *default_member)
+                    } else {
+                        None
+                    }
+                });
+                let Some((root, package)) =
+                    package.and_then(|package| Some((find_package_target(package)?, package)))
+                else {
+                    bail!("Can't find default package target");
+                };
+                (root, package)
+            }
+            PackageOrExample::Package(package) => {
+                let Some(package) = metadata.packages.iter().find(|p| p.name == *package) else {
+                    let packages = metadata
+                        .packages
+                        .iter()
+                        .map(|v| v.name.to_string())
+                        .collect::<Vec<_>>();
+                    bail!("Couldn't find package - {package} - {packages:?}");
+                };
+                let Some(p) = find_package_target(package) else {
+                    bail!("Can't find package target");
+                };
+                (p, package)
+            }
+            PackageOrExample::Example(e) => {
+                let Some((example_target, package)) = metadata
+                    .packages
+                    .iter()
+                    .flat_map(|package| package.targets.iter().map(move |t| (t, package)))
+                    .find(|(t, _)| t.is_example() && t.name == *e)
+                else {
+                    bail!("No such example");
+                };
+
+                if manifest_path.is_none() {
+                    manifest_path = Some(package.manifest_path.clone());
+                }
+                (example_target, package)
+            }
+            PackageOrExample::Bin(b) => {
+                let Some((bin_target, package)) = metadata
+                    .workspace_packages()
+                    .into_iter()
+                    .flat_map(|package| package.targets.iter().map(move |t| (t, package)))
+                    .find(|(t, _)| t.is_bin() && t.name == *b)
+                else {
+                    bail!("No such bin - {b}");
+                };
+
+                if manifest_path.is_none() {
+                    manifest_path = Some(package.manifest_path.clone());
+                }
+                (bin_target, package)
+            }
+        };
+
+        let artifact_name = built_target.name.clone();
+        let code_watch_folders = code_watch_folders(&metadata, root_package, built_target);
+        trace!("Code watch folders from cargo metadata: {code_watch_folders:?}");
+
+        let sysroot = toolchain_sysroot(working_dir, toolchain).await?;
+
+        // The toolchain files are tracked too, since changing them changes the sysroot
+        let mut manifests = vec![
+            metadata.workspace_root.join("Cargo.toml"),
+            metadata.workspace_root.join("Cargo.lock"),
+            metadata.workspace_root.join("rust-toolchain"),
+            metadata.workspace_root.join("rust-toolchain.toml"),
+        ];
+        manifests.extend(
+            metadata
+                .workspace_packages()
+                .into_iter()
+                .map(|package| package.manifest_path.clone()),
+        );
+        manifests.extend(manifest_path.iter().cloned());
+        manifests.sort();
+        manifests.dedup();
+
+        Ok(Self {
+            metadata: Arc::new(metadata),
+            artifact_name,
+            manifest_path,
+            code_watch_folders,
+            sysroot,
+            manifests: manifests
+                .into_iter()
+                .map(|path| {
+                    let modified = modified(&path);
+                    (path, modified)
+                })
+                .collect(),
+        })
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.manifests.iter().any(|(path, previous)| {
+            let stale = modified(path) != *previous;
+            if stale {
+                trace!("{path} changed since cargo metadata was loaded");
+            }
+            stale
+        })
+    }
+}
+
+fn modified(path: &Utf8Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
+pub fn is_manifest(path: &Utf8Path) -> bool {
+    matches!(path.file_name(), Some("Cargo.toml" | "Cargo.lock"))
+}
+
+/// The source directories of the target being built and every local package it depends on, along with their manifests.
+/// Otherwise only library and build script sources are watched, since other targets aren't part of the build.
+fn code_watch_folders(
+    metadata: &Metadata,
+    root: &Package,
+    built_target: &cargo_metadata::Target,
+) -> Vec<Utf8PathBuf> {
+    let mut local_packages = vec![root];
+    let mut pending = vec![&root.id];
+    let mut visited = HashSet::from([&root.id]);
+    let nodes = metadata
+        .resolve
+        .as_ref()
+        .map(|resolve| {
+            resolve
+                .nodes
+                .iter()
+                .map(|node| (&node.id, node))
+                .collect::<HashMap<_, _>>()
+        })
+        .unwrap_or_default();
+    while let Some(id) = pending.pop() {
+        let Some(node) = nodes.get(id) else {
+            continue;
+        };
+        for dependency in node.deps.iter() {
+            if !visited.insert(&dependency.pkg) {
+                continue;
+            }
+            let Some(package) = metadata.packages.iter().find(|p| p.id == dependency.pkg) else {
+                continue;
+            };
+            if package.source.is_none() {
+                local_packages.push(package);
+                pending.push(&package.id);
+            }
+        }
+    }
+
+    let mut directories = vec![];
+    let mut manifests = vec![metadata.workspace_root.join("Cargo.toml")];
+    for package in local_packages {
+        manifests.push(package.manifest_path.clone());
+        let package_root = package.manifest_path.parent();
+        let is_root = package.id == root.id;
+        for target in package.targets.iter() {
+            let is_built = is_root && target == built_target;
+            let builds_with_dependents = target.kind.iter().any(|kind| {
+                matches!(
+                    kind.as_str(),
+                    "lib"
+                        | "rlib"
+                        | "dylib"
+                        | "cdylib"
+                        | "staticlib"
+                        | "proc-macro"
+                        | "custom-build"
+                )
+            });
+            if !is_built && !builds_with_dependents {
+                continue;
+            }
+            let Some(directory) = target.src_path.parent() else {
+                continue;
+            };
+            // Sources at the package root are covered by watching the manifest
+            if Some(directory) != package_root {
+                directories.push(directory.to_owned());
+            }
+        }
+    }
+
+    directories.sort();
+    directories.dedup();
+    let nested = |path: &Utf8Path| {
+        directories
+            .iter()
+            .any(|directory| directory != path && path.starts_with(directory))
+    };
+    let mut folders = directories
+        .iter()
+        .filter(|directory| !nested(directory))
+        .cloned()
+        .collect::<Vec<_>>();
+    manifests.sort();
+    manifests.dedup();
+    folders.extend(manifests.into_iter().filter(|manifest| !nested(manifest)));
+    folders
+}
+
+fn find_package_target(package: &cargo_metadata::Package) -> Option<&cargo_metadata::Target> {
+    let targets = &package.targets;
+
+    let package_target = if let Some(lib) = targets.iter().find(|target| target.is_lib()) {
+        lib
+    } else if let Some(default_run) = &package.default_run {
+        targets
+            .iter()
+            .find(|target| target.is_bin() && &target.name == default_run)?
+    } else if let Some(first_bin) = targets.iter().find(|target| target.is_bin()) {
+        first_bin
+    } else {
+        return None;
+    };
+
+    Some(package_target)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_temp_dir::test_temp_dir;
+
+    #[tokio::test]
+    async fn metadata_is_stale_once_a_manifest_changes() {
+        let dir = test_temp_dir!();
+        let dir_path = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+
+        let _ = Command::new("cargo")
+            .current_dir(&dir_path)
+            .arg("init")
+            .arg("--lib")
+            .arg("--name=metadata_lib")
+            .arg("--vcs=none")
+            .output()
+            .await
+            .expect("Failed to create test project");
+
+        let cached = CachedMetadata::load(
+            Some(&dir_path),
+            None,
+            None,
+            &PackageOrExample::DefaulPackage,
+        )
+        .await
+        .expect("Couldn't load metadata");
+
+        assert_eq!(cached.artifact_name, "metadata_lib");
+        assert!(!cached.is_stale());
+
+        tokio::fs::write(dir_path.join("src").join("lib.rs"), "pub fn changed() {}")
+            .await
+            .unwrap();
+        assert!(!cached.is_stale());
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let manifest = dir_path.join("Cargo.toml");
+        let contents = tokio::fs::read_to_string(&manifest).await.unwrap();
+        tokio::fs::write(&manifest, format!("{contents}\n"))
+            .await
+            .unwrap();
+        assert!(cached.is_stale());
+    }
+
+    #[tokio::test]
+    async fn sysroot_is_cached_until_the_toolchain_file_changes() {
+        let dir = test_temp_dir!();
+        let dir_path = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+
+        let _ = Command::new("cargo")
+            .current_dir(&dir_path)
+            .arg("init")
+            .arg("--lib")
+            .arg("--name=toolchain_lib")
+            .arg("--vcs=none")
+            .output()
+            .await
+            .expect("Failed to create test project");
+
+        let cached = CachedMetadata::load(
+            Some(&dir_path),
+            None,
+            None,
+            &PackageOrExample::DefaulPackage,
+        )
+        .await
+        .expect("Couldn't load metadata");
+
+        let sysroot = toolchain_sysroot(Some(&dir_path), None)
+            .await
+            .expect("Couldn't find sysroot");
+        assert_eq!(cached.sysroot, sysroot);
+        assert!(!cached.is_stale());
+
+        tokio::fs::write(
+            dir_path.join("rust-toolchain.toml"),
+            "[toolchain]\nchannel = \"stable\"\n",
+        )
+        .await
+        .unwrap();
+        assert!(cached.is_stale());
+    }
+
+    #[tokio::test]
+    async fn code_watch_folders_include_local_dependencies() {
+        let dir = test_temp_dir!();
+        let root = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf())
+            .unwrap()
+            .canonicalize_utf8()
+            .unwrap();
+
+        let files = [
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"app\", \"helper\"]\nresolver = \"2\"\n",
+            ),
+            (
+                "app/Cargo.toml",
+                "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nhelper = { path = \"../helper\" }\n",
+            ),
+            ("app/src/lib.rs", ""),
+            ("app/examples/demo.rs", "fn main() {}"),
+            (
+                "helper/Cargo.toml",
+                "[package]\nname = \"helper\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+            ),
+            ("helper/src/lib.rs", ""),
+            ("helper/examples/unrelated.rs", "fn main() {}"),
+        ];
+        for (path, contents) in files {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        let cached = CachedMetadata::load(
+            Some(&root),
+            None,
+            None,
+            &PackageOrExample::Package("app".to_string()),
+        )
+        .await
+        .expect("Couldn't load metadata");
+
+        assert_eq!(
+            cached.code_watch_folders,
+            vec![
+                root.join("app").join("src"),
+                root.join("helper").join("src"),
+                root.join("Cargo.toml"),
+                root.join("app").join("Cargo.toml"),
+                root.join("helper").join("Cargo.toml"),
+            ]
+        );
+
+        let cached = CachedMetadata::load(
+            Some(&root),
+            None,
+            None,
+            &PackageOrExample::Example("demo".to_string()),
+        )
+        .await
+        .expect("Couldn't load metadata");
+
+        assert_eq!(
+            cached.code_watch_folders,
+            vec![
+                root.join("app").join("examples"),
+                root.join("app").join("src"),
+                root.join("helper").join("src"),
+                root.join("Cargo.toml"),
+                root.join("app").join("Cargo.toml"),
+                root.join("helper").join("Cargo.toml"),
+            ]
+        );
+    }
+
+    #[test]
+    fn recognizes_manifests() {
+        assert!(is_manifest(Utf8Path::new("/project/Cargo.toml")));
+        assert!(is_manifest(Utf8Path::new("Cargo.lock")));
+        assert!(!is_manifest(Utf8Path::new("/project/src/main.rs")));
+    }
+}
@@ -1,2 +1,6 @@
 pub mod builder;
+pub mod metadata;
+pub mod retention;
+pub mod rust_flags;
 pub mod rustc;
+pub mod toolchain;
@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+
+use camino::Utf8PathBuf;
+use tracing::{debug, trace};
+
+pub const DEFAULT_RETAINED_BUILDS: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct BuildArtifact {
+    pub id: u32,
+    pub name: String,
+    pub path: Utf8PathBuf,
+}
+
+/// Removes every artifact that isn't one of the `retained` most recent builds and isn't referenced by a connected runner,
+/// returning the removed artifacts so their files can be deleted.
+pub fn collect_unreferenced(
+    artifacts: &mut Vec<BuildArtifact>,
+    referenced: &HashSet<u32>,
+    retained: usize,
+) -> Vec<BuildArtifact> {
+    artifacts.sort_by_key(|artifact| artifact.id);
+    let newest_retained = artifacts.len().saturating_sub(retained.max(1));
+
+    let mut index = 0;
+    let mut removed = vec![];
+    artifacts.retain(|artifact| {
+        let keep = index >= newest_retained || referenced.contains(&artifact.id);
+        index += 1;
+        if !keep {
+            removed.push(artifact.clone());
+        }
+        keep
+    });
+    removed
+}
+
+pub async fn delete_artifact(artifact: &BuildArtifact) -> anyhow::Result<()> {
+    trace!("Removing build {} - {}", artifact.id, artifact.path);
+    let Some(directory) = artifact.path.parent() else {
+        return Ok(());
+    };
+
+    let prefix = format!("{}.", artifact.name);
+    let lib_prefix = format!("lib{prefix}");
+
+    let mut dir = tokio::fs::read_dir(directory).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let Ok(file_name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if !file_name.starts_with(&prefix) && !file_name.starts_with(&lib_prefix) {
+            continue;
+        }
+        if !entry.file_type().await?.is_file() {
+            continue;
+        }
+        if let Err(e) = tokio::fs::remove_file(entry.path()).await {
+            debug!("Couldn't remove {file_name} - {e}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_temp_dir::test_temp_dir;
+
+    fn artifacts(ids: &[u32]) -> Vec<BuildArtifact> {
+        ids.iter()
+            .map(|id| BuildArtifact {
+                id: *id,
+                name: format!("test_lib.{id}"),
+                path: Utf8PathBuf::from(format!("libtest_lib.{id}.so")),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_most_recent_builds() {
+        let mut tracked = artifacts(&[1, 2, 3, 4, 5]);
+
+        let removed = collect_unreferenced(&mut tracked, &HashSet::new(), 2);
+
+        assert_eq!(
+            removed.iter().map(|a| a.id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(tracked.iter().map(|a| a.id).collect::<Vec<_>>(), vec![4, 5]);
+    }
+
+    #[test]
+    fn keeps_builds_referenced_by_runners() {
+        let mut tracked = artifacts(&[1, 2, 3, 4, 5]);
+
+        let removed = collect_unreferenced(&mut tracked, &[1, 3].into_iter().collect(), 1);
+
+        assert_eq!(removed.iter().map(|a| a.id).collect::<Vec<_>>(), vec![2, 4]);
+        assert_eq!(
+            tracked.iter().map(|a| a.id).collect::<Vec<_>>(),
+            vec![1, 3, 5]
+        );
+    }
+
+    #[test]
+    fn always_keeps_the_latest_build() {
+        let mut tracked = artifacts(&[1, 2]);
+
+        let removed = collect_unreferenced(&mut tracked, &HashSet::new(), 0);
+
+        assert_eq!(removed.iter().map(|a| a.id).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(tracked.iter().map(|a| a.id).collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[tokio::test]
+    async fn deleting_an_artifact_removes_its_files_only() {
+        let temp_dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(temp_dir.as_path_untracked().to_path_buf()).unwrap();
+
+        for file in [
+            "libtest_lib.1.so",
+            "test_lib.1.d",
+            "libtest_lib.10.so",
+            "libtest_lib.2.so",
+        ] {
+            tokio::fs::write(dir.join(file), file).await.unwrap();
+        }
+
+        delete_artifact(&BuildArtifact {
+            id: 1,
+            name: "test_lib.1".to_string(),
+            path: dir.join("libtest_lib.1.so"),
+        })
+        .await
+        .expect("Couldn't delete artifact");
+
+        assert!(!dir.join("libtest_lib.1.so").exists());
+        assert!(!dir.join("test_lib.1.d").exists());
+        assert!(dir.join("libtest_lib.10.so").exists());
+        assert!(dir.join("libtest_lib.2.so").exists());
+    }
+}
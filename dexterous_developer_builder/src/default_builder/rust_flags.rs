@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use cargo_platform::{Cfg, Platform};
+use dexterous_developer_types::{Linker, Target};
+use tracing::{debug, trace};
+
+/// The rustflags cargo would have used for this target on its own - either from the process environment,
+/// the build's configured environment, or the `.cargo/config.toml` files that apply to the working directory.
+pub fn user_rustflags(
+    working_dir: &Utf8Path,
+    target: Target,
+    environment: &HashMap<String, String>,
+) -> Vec<String> {
+    let var = |name: &str| {
+        environment
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+    };
+
+    if let Some(encoded) = var("CARGO_ENCODED_RUSTFLAGS") {
+        return encoded
+            .split('\x1f')
+            .filter(|flag| !flag.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+    }
+    if let Some(flags) = var("RUSTFLAGS") {
+        return split_flags(&flags);
+    }
+
+    let configs = config_files(working_dir)
+        .into_iter()
+        .filter_map(|path| {
+            let file = std::fs::read_to_string(&path).ok()?;
+            match toml::from_str::<toml::Table>(&file) {
+                Ok(config) => {
+                    trace!("Reading rustflags from {path}");
+                    Some(config)
+                }
+                Err(e) => {
+                    debug!("Couldn't parse cargo config at {path} - {e}");
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let target_env = format!(
+        "CARGO_TARGET_{}_RUSTFLAGS",
+        target.as_str().to_uppercase().replace('-', "_")
+    );
+
+    let mut target_flags = target_config_rustflags(&configs, target);
+    if let Some(flags) = var(&target_env) {
+        target_flags.extend(split_flags(&flags));
+    }
+    if !target_flags.is_empty() {
+        return target_flags;
+    }
+
+    let mut build_flags = config_rustflags(&configs, &["build"]);
+    if let Some(flags) = var("CARGO_BUILD_RUSTFLAGS") {
+        build_flags.extend(split_flags(&flags));
+    }
+    build_flags
+}
+
+/// Combines the user's rustflags with the ones hot reloading needs, so the result can be passed on as `CARGO_ENCODED_RUSTFLAGS`.
+pub fn merge_rustflags(user_flags: Vec<String>, linker: &Linker, target: Target) -> Vec<String> {
+    let user_selected_linker = user_flags
+        .iter()
+        .any(|flag| flag.contains("fuse-ld") || flag.contains("linker="));
+    let linker = if user_selected_linker && *linker == Linker::Default {
+        &Linker::System
+    } else {
+        linker
+    };
+
+    let mut flags = user_flags;
+    flags.push("-Cprefer-dynamic".to_string());
+    flags.extend(linker.rustflags(target));
+    flags
+}
+
+/// Merges hot reloading's rustflags into a cargo command that was already prepared elsewhere, such as the one cargo-zigbuild
+/// hands back. Rustflags already set on the command come first, and if it configured a target linker that linker is left in charge.
+pub fn prepared_command_rustflags(
+    command: &std::process::Command,
+    user_flags: Vec<String>,
+    linker: &Linker,
+    target: Target,
+) -> Vec<String> {
+    let mut existing = vec![];
+    let mut sets_linker = false;
+    for (key, value) in command.get_envs() {
+        let (Some(key), Some(value)) = (key.to_str(), value.and_then(|value| value.to_str()))
+        else {
+            continue;
+        };
+        match key {
+            "CARGO_ENCODED_RUSTFLAGS" => existing.extend(
+                value
+                    .split('\x1f')
+                    .filter(|flag| !flag.is_empty())
+                    .map(ToOwned::to_owned),
+            ),
+            "RUSTFLAGS" => existing.extend(split_flags(value)),
+            key if key.starts_with("CARGO_TARGET_") && key.ends_with("_LINKER") => {
+                sets_linker = true;
+            }
+            _ => {}
+        }
+    }
+
+    let linker = if sets_linker { &Linker::System } else { linker };
+    existing.extend(user_flags);
+    merge_rustflags(existing, linker, target)
+}
+
+/// Cargo config files, ordered from lowest to highest precedence.
+fn config_files(working_dir: &Utf8Path) -> Vec<Utf8PathBuf> {
+    let mut directories = working_dir
+        .ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .collect::<Vec<_>>();
+    if let Some(cargo_home) = home::cargo_home()
+        .ok()
+        .and_then(|home| Utf8PathBuf::from_path_buf(home).ok())
+    {
+        if !directories.contains(&cargo_home) {
+            directories.push(cargo_home);
+        }
+    }
+
+    directories
+        .into_iter()
+        .rev()
+        .filter_map(|dir| {
+            ["config.toml", "config"]
+                .into_iter()
+                .map(|file| dir.join(file))
+                .find(|path| path.is_file())
+        })
+        .collect()
+}
+
+fn config_rustflags(configs: &[toml::Table], keys: &[&str]) -> Vec<String> {
+    configs
+        .iter()
+        .filter_map(|config| {
+            let mut table = config;
+            for key in keys {
+                table = table.get(*key)?.as_table()?;
+            }
+            table.get("rustflags")
+        })
+        .flat_map(flag_values)
+        .collect()
+}
+
+/// Rustflags from every `[target.<triple>]` and `[target.'cfg(...)']` table that applies to the target, like cargo joins them
+fn target_config_rustflags(configs: &[toml::Table], target: Target) -> Vec<String> {
+    let cfgs = target_cfgs(target);
+    configs
+        .iter()
+        .filter_map(|config| config.get("target")?.as_table())
+        .flat_map(|targets| targets.iter())
+        .filter(|(key, _)| {
+            key.parse::<Platform>()
+                .is_ok_and(|platform| platform.matches(target.as_str(), &cfgs))
+        })
+        .filter_map(|(_, table)| table.as_table()?.get("rustflags"))
+        .flat_map(flag_values)
+        .collect()
+}
+
+/// The cfgs `[target.'cfg(...)']` tables are evaluated against. Target features aren't known ahead of time,
+/// so tables that depend on them never apply.
+fn target_cfgs(target: Target) -> Vec<Cfg> {
+    let (arch, os, env, vendor, family) = match target {
+        Target::Linux => ("x86_64", "linux", "gnu", "unknown", "unix"),
+        Target::LinuxArm => ("aarch64", "linux", "gnu", "unknown", "unix"),
+        Target::Windows => ("x86_64", "windows", "msvc", "pc", "windows"),
+        Target::Mac => ("x86_64", "macos", "", "apple", "unix"),
+        Target::MacArm => ("aarch64", "macos", "", "apple", "unix"),
+        Target::Android => ("aarch64", "android", "", "unknown", "unix"),
+        Target::IOS => ("aarch64", "ios", "", "apple", "unix"),
+    };
+    let key_value = |key: &str, value: &str| Cfg::KeyPair(key.to_string(), value.to_string());
+    vec![
+        Cfg::Name(family.to_string()),
+        key_value("target_family", family),
+        key_value("target_arch", arch),
+        key_value("target_os", os),
+        key_value("target_env", env),
+        key_value("target_vendor", vendor),
+        key_value("target_pointer_width", "64"),
+        key_value("target_endian", "little"),
+        Cfg::Name("debug_assertions".to_string()),
+    ]
+}
+
+fn flag_values(flags: &toml::Value) -> Vec<String> {
+    match flags {
+        toml::Value::String(flags) => split_flags(flags),
+        toml::Value::Array(flags) => flags
+            .iter()
+            .filter_map(|flag| flag.as_str().map(ToOwned::to_owned))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn split_flags(flags: &str) -> Vec<String> {
+    flags.split_whitespace().map(ToOwned::to_owned).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn configs(files: &[&str]) -> Vec<toml::Table> {
+        files
+            .iter()
+            .map(|file| toml::from_str(file).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn target_rustflags_are_joined_across_config_files() {
+        let configs = configs(&[
+            r#"
+            [target.x86_64-unknown-linux-gnu]
+            rustflags = "--cfg home"
+            "#,
+            r#"
+            [build]
+            rustflags = ["--cfg", "build"]
+
+            [target.x86_64-unknown-linux-gnu]
+            rustflags = ["-Ctarget-cpu=native"]
+            "#,
+        ]);
+
+        assert_eq!(
+            config_rustflags(&configs, &["target", Target::Linux.as_str()]),
+            vec!["--cfg", "home", "-Ctarget-cpu=native"]
+        );
+        assert_eq!(
+            config_rustflags(&configs, &["build"]),
+            vec!["--cfg", "build"]
+        );
+        assert!(config_rustflags(&configs, &["target", Target::Windows.as_str()]).is_empty());
+    }
+
+    #[test]
+    fn cfg_target_rustflags_apply_when_they_match() {
+        let configs = configs(&[r#"
+            [target.'cfg(target_os = "linux")']
+            rustflags = ["--cfg", "linux"]
+
+            [target.'cfg(all(unix, not(target_os = "linux")))']
+            rustflags = ["--cfg", "other_unix"]
+
+            [target.'cfg(windows)']
+            rustflags = ["--cfg", "windows"]
+
+            [target.x86_64-unknown-linux-gnu]
+            rustflags = ["-Ctarget-cpu=native"]
+            "#]);
+
+        assert_eq!(
+            target_config_rustflags(&configs, Target::Linux),
+            vec!["--cfg", "linux", "-Ctarget-cpu=native"]
+        );
+        assert_eq!(
+            target_config_rustflags(&configs, Target::MacArm),
+            vec!["--cfg", "other_unix"]
+        );
+        assert_eq!(
+            target_config_rustflags(&configs, Target::Windows),
+            vec!["--cfg", "windows"]
+        );
+    }
+
+    #[test]
+    fn merging_keeps_user_flags_and_adds_the_linker() {
+        let flags = merge_rustflags(
+            vec!["-Ctarget-cpu=native".to_string()],
+            &Linker::Mold,
+            Target::Linux,
+        );
+
+        assert_eq!(
+            flags,
+            vec![
+                "-Ctarget-cpu=native",
+                "-Cprefer-dynamic",
+                "-Clink-arg=-fuse-ld=mold"
+            ]
+        );
+    }
+
+    #[test]
+    fn default_linker_defers_to_a_user_selected_linker() {
+        let user_flags = vec!["-Clink-arg=-fuse-ld=mold".to_string()];
+
+        assert_eq!(
+            merge_rustflags(user_flags.clone(), &Linker::Default, Target::Linux),
+            vec!["-Clink-arg=-fuse-ld=mold", "-Cprefer-dynamic"]
+        );
+        assert_eq!(
+            merge_rustflags(vec![], &Linker::Default, Target::Linux),
+            vec!["-Cprefer-dynamic", "-Clink-arg=-fuse-ld=lld"]
+        );
+    }
+
+    #[test]
+    fn prepared_commands_keep_their_rustflags_and_linker() {
+        let mut command = std::process::Command::new("cargo");
+        command
+            .env(
+                "CARGO_ENCODED_RUSTFLAGS",
+                "-Ctarget-cpu=generic\x1f-Ctarget-feature=+crt-static",
+            )
+            .env(
+                "CARGO_TARGET_AARCH64_UNKNOWN_LINUX_GNU_LINKER",
+                "/tmp/zigcc",
+            );
+
+        assert_eq!(
+            prepared_command_rustflags(
+                &command,
+                vec!["--cfg".to_string(), "hot".to_string()],
+                &Linker::Mold,
+                Target::LinuxArm
+            ),
+            vec![
+                "-Ctarget-cpu=generic",
+                "-Ctarget-feature=+crt-static",
+                "--cfg",
+                "hot",
+                "-Cprefer-dynamic"
+            ]
+        );
+    }
+
+    #[test]
+    fn prepared_commands_without_a_linker_get_the_selected_one() {
+        let mut command = std::process::Command::new("cargo");
+        command.env("RUSTFLAGS", "-Copt-level=1");
+
+        assert_eq!(
+            prepared_command_rustflags(&command, vec![], &Linker::Mold, Target::Linux),
+            vec![
+                "-Copt-level=1",
+                "-Cprefer-dynamic",
+                "-Clink-arg=-fuse-ld=mold"
+            ]
+        );
+    }
+}
@@ -2,6 +2,7 @@ use std::{process::Stdio, str::FromStr};
 
 use anyhow::{anyhow, bail};
 use camino::Utf8PathBuf;
+use dexterous_developer_types::Target;
 
 use super::builder::DefaultRunParams;
 
@@ -10,10 +11,20 @@ pub async fn default_rustc() -> anyhow::Result<()> {
     let output_file = std::env::var("DEXTEROUS_DEVELOPER_OUTPUT_FILE")?;
     let default_run_params: DefaultRunParams =
         serde_json::from_str(&std::env::var("DEXTEROUS_DEVELOPER_DEFAULT_RUN")?)?;
+    let crate_type = std::env::var("DEXTEROUS_DEVELOPER_CRATE_TYPE")
+        .ok()
+        .filter(|crate_type| !crate_type.is_empty());
+    let reloadable_crates: Vec<String> = std::env::var("DEXTEROUS_DEVELOPER_RELOADABLE_CRATES")
+        .ok()
+        .map(|crates| serde_json::from_str(&crates))
+        .transpose()?
+        .unwrap_or_default();
 
     let rustc = Rustc::new(
         std::env::args(),
         &package_name,
+        crate_type.as_deref(),
+        &reloadable_crates,
         &output_file,
         &default_run_params,
     )
@@ -46,6 +57,7 @@ enum RustcOperation {
         out_dir: Utf8PathBuf,
         file_name_extras: String,
         target: String,
+        is_root: bool,
         search_paths: Vec<String>,
         library_links: Vec<String>,
         extern_links: Vec<String>,
@@ -56,6 +68,8 @@ impl Rustc {
     async fn new(
         mut args: impl Iterator<Item = String>,
         package: &str,
+        root_crate_type: Option<&str>,
+        reloadable_crates: &[String],
         output_file: &str,
         run_params: &DefaultRunParams,
     ) -> anyhow::Result<Self> {
@@ -85,13 +99,34 @@ impl Rustc {
             }
         }
 
+        let output_dir = Utf8PathBuf::from(output_file)
+            .parent()
+            .ok_or(anyhow!("No Parent for Output File"))?
+            .to_owned();
+
+        let file_name_extras = match run_params {
+            DefaultRunParams::InitialRun => ".1".to_string(),
+            DefaultRunParams::Patch { id, .. } => format!(".{id}"),
+        };
+
+        let args =
+            rewrite_reloadable_externs(args, reloadable_crates, &output_dir, &file_name_extras);
+
         let operation = {
             let mut is_passthrough = true;
+            let mut is_root = false;
             let mut args_iter = args.iter();
             while let Some(arg) = args_iter.next() {
                 if arg.as_str() == "--crate-name" {
                     if let Some(name) = args_iter.next() {
-                        if name.as_str() == package {
+                        if *name == package.replace('-', "_")
+                            && root_crate_type.is_none_or(|ty| crate_type(&args) == Some(ty))
+                        {
+                            is_passthrough = false;
+                            is_root = true;
+                            break;
+                        }
+                        if reloadable_crates.contains(name) && is_reloadable_library(&args) {
                             is_passthrough = false;
                             break;
                         }
@@ -170,16 +205,6 @@ impl Rustc {
                     }
                 }
 
-                let output_dir = Utf8PathBuf::from(output_file)
-                    .parent()
-                    .ok_or(anyhow!("No Parent for Output File"))?
-                    .to_owned();
-
-                let file_name_extras = match run_params {
-                    DefaultRunParams::InitialRun => ".1".to_string(),
-                    DefaultRunParams::Patch { id, .. } => format!(".{id}"),
-                };
-
                 RustcOperation::MainCompilation {
                     crate_name: crate_name.ok_or(anyhow!("Couldn't determine crate name"))?,
                     edition,
@@ -195,6 +220,7 @@ impl Rustc {
                     library_links,
                     extern_links,
                     file_name_extras,
+                    is_root,
                 }
             }
         };
@@ -225,11 +251,19 @@ impl Rustc {
                 library_links,
                 extern_links,
                 file_name_extras,
+                is_root,
                 ..
             } => {
+                // Without the artifact notifications cargo waits for reloadable crates to finish linking
+                // before compiling the crates that depend on them, rather than pipelining on their metadata.
+                let json = if is_root {
+                    "--json=diagnostic-rendered-ansi,artifacts,future-incompat"
+                } else {
+                    "--json=diagnostic-rendered-ansi,future-incompat"
+                };
                 command
                     .arg("--error-format=json")
-                    .arg("--json=diagnostic-rendered-ansi,artifacts,future-incompat")
+                    .arg(json)
                     .arg("--crate-name")
                     .arg(crate_name)
                     .arg("--edition")
@@ -288,6 +322,68 @@ impl Rustc {
     }
 }
 
+fn crate_type(args: &[String]) -> Option<&str> {
+    args.iter()
+        .skip_while(|arg| !arg.starts_with("--crate-type"))
+        .nth(1)
+        .map(|crate_type| crate_type.as_str())
+}
+
+fn is_reloadable_library(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--target")
+        && matches!(crate_type(args), Some("lib" | "rlib" | "dylib"))
+}
+
+/// Points `--extern` arguments for reloadable crates at the dylibs built for this run, instead of the rlibs cargo expects.
+fn rewrite_reloadable_externs(
+    args: Vec<String>,
+    reloadable_crates: &[String],
+    out_dir: &Utf8PathBuf,
+    file_name_extras: &str,
+) -> Vec<String> {
+    if reloadable_crates.is_empty() {
+        return args;
+    }
+    let Some(target) = args
+        .iter()
+        .skip_while(|arg| arg.as_str() != "--target")
+        .nth(1)
+        .and_then(|target| Target::from_str(target).ok())
+    else {
+        return args;
+    };
+
+    let mut rewritten = Vec::with_capacity(args.len());
+    let mut is_extern = false;
+    for arg in args {
+        if !is_extern {
+            is_extern = arg == "--extern";
+            rewritten.push(arg);
+            continue;
+        }
+        is_extern = false;
+
+        let Some((name, path)) = arg.split_once('=') else {
+            rewritten.push(arg);
+            continue;
+        };
+        let crate_name = Utf8PathBuf::from(path)
+            .file_stem()
+            .and_then(|stem| stem.strip_prefix("lib"))
+            .and_then(|stem| stem.rsplit_once('-'))
+            .map(|(crate_name, _)| crate_name.to_string());
+
+        match crate_name {
+            Some(crate_name) if reloadable_crates.contains(&crate_name) => {
+                let library = target.dynamic_lib_name(&format!("{crate_name}{file_name_extras}"));
+                rewritten.push(format!("{name}={}", out_dir.join(library)));
+            }
+            _ => rewritten.push(arg),
+        }
+    }
+    rewritten
+}
+
 struct WrappedCommand {
     executable: String,
     arguments: Vec<String>,
@@ -338,3 +434,62 @@ impl WrappedCommand {
         Ok(cmd)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn externs_for_reloadable_crates_point_at_versioned_dylibs() {
+        let rewritten = rewrite_reloadable_externs(
+            args(&[
+                "--crate-name",
+                "game",
+                "--target",
+                "x86_64-unknown-linux-gnu",
+                "--extern",
+                "gameplay=/target/deps/libgameplay-0123abcd.rmeta",
+                "--extern",
+                "serde=/target/deps/libserde-4567ef01.rlib",
+            ]),
+            &["gameplay".to_string()],
+            &Utf8PathBuf::from("/target/debug"),
+            ".3",
+        );
+
+        assert_eq!(
+            rewritten,
+            args(&[
+                "--crate-name",
+                "game",
+                "--target",
+                "x86_64-unknown-linux-gnu",
+                "--extern",
+                "gameplay=/target/debug/libgameplay.3.so",
+                "--extern",
+                "serde=/target/deps/libserde-4567ef01.rlib",
+            ])
+        );
+    }
+
+    #[test]
+    fn only_target_libraries_are_reloadable() {
+        assert!(is_reloadable_library(&args(&[
+            "--crate-type",
+            "lib",
+            "--target",
+            "x86_64-unknown-linux-gnu"
+        ])));
+        assert!(!is_reloadable_library(&args(&["--crate-type", "lib"])));
+        assert!(!is_reloadable_library(&args(&[
+            "--crate-type",
+            "proc-macro",
+            "--target",
+            "x86_64-unknown-linux-gnu"
+        ])));
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::bail;
+use camino::{Utf8Path, Utf8PathBuf};
+use dexterous_developer_types::Target;
+use tokio::process::Command;
+use tracing::trace;
+
+/// Finds the sysroot of the toolchain used for a build. Without an explicit toolchain,
+/// rustup resolves it from `rust-toolchain.toml` relative to the working directory.
+pub async fn toolchain_sysroot(
+    working_dir: Option<&Utf8Path>,
+    toolchain: Option<&str>,
+) -> anyhow::Result<Utf8PathBuf> {
+    let mut cmd = Command::new("rustc");
+    cmd.arg("--print").arg("sysroot");
+    if let Some(working_dir) = working_dir {
+        cmd.current_dir(working_dir);
+    }
+    if let Some(toolchain) = toolchain {
+        cmd.env("RUSTUP_TOOLCHAIN", toolchain);
+    }
+
+    let output = cmd.output().await?;
+    if !output.status.success() {
+        bail!(
+            "Couldn't determine sysroot for toolchain {} - {}",
+            toolchain.unwrap_or("default"),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let sysroot = Utf8PathBuf::from(String::from_utf8(output.stdout)?.trim());
+    trace!("Toolchain sysroot: {sysroot}");
+    Ok(sysroot)
+}
+
+pub fn toolchain_library_directories(sysroot: &Utf8Path, target: Target) -> Vec<Utf8PathBuf> {
+    vec![
+        sysroot.join("lib"),
+        sysroot
+            .join("lib")
+            .join("rustlib")
+            .join(target.as_str())
+            .join("lib"),
+    ]
+}
+
+/// Whether a library directory belongs to a rustup toolchain other than the one in use.
+pub fn is_other_toolchain(directory: &Utf8Path, sysroot: &Utf8Path) -> bool {
+    let Some(toolchains) = home::rustup_home()
+        .ok()
+        .and_then(|home| Utf8PathBuf::from_path_buf(home.join("toolchains")).ok())
+    else {
+        return false;
+    };
+    directory.starts_with(toolchains) && !directory.starts_with(sysroot)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn library_directories_include_the_target_std() {
+        let sysroot = Utf8Path::new("/toolchains/stable");
+
+        assert_eq!(
+            toolchain_library_directories(sysroot, Target::Android),
+            vec![
+                Utf8PathBuf::from("/toolchains/stable/lib"),
+                Utf8PathBuf::from("/toolchains/stable/lib/rustlib/aarch64-linux-android/lib"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn can_find_the_current_sysroot() {
+        let sysroot = toolchain_sysroot(None, None)
+            .await
+            .expect("Couldn't find sysroot");
+
+        assert!(sysroot.join("lib").exists());
+        assert!(!is_other_toolchain(&sysroot.join("lib"), &sysroot));
+    }
+}
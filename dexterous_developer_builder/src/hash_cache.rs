@@ -0,0 +1,143 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::SystemTime,
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, trace};
+
+const HASH_CACHE_PATH: &str = "./target/hot-reload/file-hashes.json";
+
+static SHARED_CACHE: OnceLock<Arc<HashCache>> = OnceLock::new();
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct CachedHash {
+    size: u64,
+    modified: SystemTime,
+    hash: [u8; 32],
+}
+
+#[derive(Debug, Default)]
+pub struct HashCache {
+    path: Option<Utf8PathBuf>,
+    entries: DashMap<Utf8PathBuf, CachedHash>,
+    dirty: AtomicBool,
+}
+
+impl HashCache {
+    /// The cache shared by the builders and watchers in this process, stored under `target/hot-reload`.
+    pub fn shared() -> Arc<Self> {
+        SHARED_CACHE
+            .get_or_init(|| Arc::new(Self::load(HASH_CACHE_PATH)))
+            .clone()
+    }
+
+    pub fn load(path: impl Into<Utf8PathBuf>) -> Self {
+        let path = path.into();
+        let entries = std::fs::read(&path)
+            .ok()
+            .and_then(|file| {
+                serde_json::from_slice::<Vec<(Utf8PathBuf, CachedHash)>>(&file)
+                    .map_err(|e| debug!("Couldn't parse hash cache at {path} - {e}"))
+                    .ok()
+            })
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(path, _)| path.exists())
+            .collect();
+
+        Self {
+            path: Some(path),
+            entries,
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn hash_file(&self, path: &Utf8Path) -> std::io::Result<[u8; 32]> {
+        let metadata = std::fs::metadata(path)?;
+        let size = metadata.len();
+        let modified = metadata.modified()?;
+
+        if let Some(cached) = self.entries.get(path) {
+            if cached.size == size && cached.modified == modified {
+                trace!("Using cached hash for {path}");
+                return Ok(cached.hash);
+            }
+        }
+
+        trace!("Hashing {path}");
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader(std::fs::File::open(path)?)?;
+        let hash = hasher.finalize().as_bytes().to_owned();
+
+        self.entries.insert(
+            path.to_owned(),
+            CachedHash {
+                size,
+                modified,
+                hash,
+            },
+        );
+        self.dirty.store(true, Ordering::SeqCst);
+        Ok(hash)
+    }
+
+    pub fn persist(&self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect::<Vec<_>>();
+        let file = serde_json::to_vec(&entries)?;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temporary = path.with_extension("json.tmp");
+        std::fs::write(&temporary, file)?;
+        std::fs::rename(&temporary, path)?;
+        trace!("Persisted {} hashes to {path}", entries.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_temp_dir::test_temp_dir;
+
+    #[test]
+    fn reuses_hashes_until_the_file_changes() {
+        let temp_dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(temp_dir.as_path_untracked().to_path_buf()).unwrap();
+        let file = dir.join("asset.txt");
+        std::fs::write(&file, "first").unwrap();
+
+        let cache = HashCache::load(dir.join("hashes.json"));
+        let hash = cache.hash_file(&file).expect("Couldn't hash file");
+        assert_eq!(&hash, blake3::hash(b"first").as_bytes());
+        cache.persist().expect("Couldn't persist cache");
+
+        let cache = HashCache::load(dir.join("hashes.json"));
+        assert!(cache.entries.contains_key(&file));
+        assert_eq!(cache.hash_file(&file).expect("Couldn't hash file"), hash);
+
+        std::fs::write(&file, "second, and longer").unwrap();
+        assert_eq!(
+            &cache.hash_file(&file).expect("Couldn't hash file"),
+            blake3::hash(b"second, and longer").as_bytes()
+        );
+    }
+}
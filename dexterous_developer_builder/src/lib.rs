@@ -4,4 +4,12 @@ pub mod types;
 
 pub mod simple_watcher;
 
+pub mod watch_filter;
+
+pub mod hash_cache;
+
+pub mod asset_processor;
+
 pub mod default_builder;
+
+pub mod zig_builder;
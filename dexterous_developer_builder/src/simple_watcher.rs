@@ -1,26 +1,66 @@
-use std::env;
+use std::{env, time::Duration};
 
 use camino::{Utf8Path, Utf8PathBuf};
 use dashmap::DashMap;
 
-use notify::{RecommendedWatcher, Watcher as NotifyWatcher};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{EventHandler, EventKind, PollWatcher, Watcher as NotifyWatcher};
 use tokio::sync::broadcast::{self};
-use tracing::{info, trace};
+use tracing::{debug, info, trace};
 
+use crate::default_builder::metadata::is_manifest;
+use crate::hash_cache::HashCache;
 use crate::types::{BuilderIncomingMessages, HashedFileRecord, Watcher, WatcherError};
+use crate::watch_filter::{is_code_event, watch_root, CodeWatchFilter};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WatchBackend {
+    #[default]
+    Native,
+    /// Scans the watched directories on an interval instead of relying on OS events,
+    /// which never arrive on things like docker bind mounts, WSL mounted drives or NFS.
+    Poll(Duration),
+}
+
+type BoxedWatcher = Box<dyn NotifyWatcher + Send + Sync>;
 
 pub struct SimpleWatcher {
     channel: tokio::sync::broadcast::Sender<BuilderIncomingMessages>,
-    watchers: DashMap<Utf8PathBuf, RecommendedWatcher>,
+    watchers: DashMap<Utf8PathBuf, BoxedWatcher>,
+    code_filter: CodeWatchFilter,
+    backend: WatchBackend,
 }
 
 impl Default for SimpleWatcher {
     fn default() -> Self {
+        Self::new(CodeWatchFilter::default())
+    }
+}
+
+impl SimpleWatcher {
+    pub fn new(code_filter: CodeWatchFilter) -> Self {
         Self {
             channel: broadcast::channel(100).0,
             watchers: Default::default(),
+            code_filter,
+            backend: WatchBackend::default(),
         }
     }
+
+    pub fn with_backend(mut self, backend: WatchBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    fn create_watcher(&self, handler: impl EventHandler) -> Result<BoxedWatcher, notify::Error> {
+        Ok(match self.backend {
+            WatchBackend::Native => Box::new(notify::recommended_watcher(handler)?),
+            WatchBackend::Poll(interval) => Box::new(PollWatcher::new(
+                handler,
+                notify::Config::default().with_poll_interval(interval),
+            )?),
+        })
+    }
 }
 
 impl Watcher for SimpleWatcher {
@@ -40,20 +80,52 @@ impl Watcher for SimpleWatcher {
                 .entry(directory.clone())
                 .or_try_insert_with::<WatcherError>(|| {
                     trace!("Adding watcher entry");
-                    let directory = directory.clone();
+                    // Files are watched through their directory, so editors that save by replacing the file don't break the watch
+                    let (directory, mode) = match directory.parent() {
+                        Some(parent) if directory.is_file() => {
+                            (parent.to_owned(), notify::RecursiveMode::NonRecursive)
+                        }
+                        _ => (directory.clone(), notify::RecursiveMode::Recursive),
+                    };
 
                     let mut watcher = {
                         let channel = self.channel.clone();
-                        notify::recommended_watcher(move |_| {
-                            info!("Got Watch Event");
-                            let _ = channel.send(BuilderIncomingMessages::CodeChanged);
+                        let filter = self.code_filter.clone();
+                        let gitignore = filter.gitignore_for(&directory);
+                        let root = watch_root(&directory);
+                        self.create_watcher(move |event: Result<notify::Event, notify::Error>| {
+                            let event = match event {
+                                Ok(event) => event,
+                                Err(e) => {
+                                    debug!("Watch Error - {e}");
+                                    return;
+                                }
+                            };
+                            if !is_code_event(&event.kind) {
+                                trace!("Ignoring {:?} event", event.kind);
+                                return;
+                            }
+                            let paths = event
+                                .paths
+                                .into_iter()
+                                .filter_map(|path| Utf8PathBuf::from_path_buf(path).ok())
+                                .filter(|path| filter.matches(path, &root, &gitignore))
+                                .collect::<Vec<_>>();
+                            if paths.is_empty() {
+                                return;
+                            }
+                            info!("Code Changed - {paths:?}");
+                            if paths.iter().any(|path| is_manifest(path)) {
+                                let _ = channel.send(BuilderIncomingMessages::ManifestChanged);
+                            }
+                            let _ = channel.send(BuilderIncomingMessages::CodeChanged(paths));
                             trace!("Finished Sending Code Changed Messages");
                         })?
                     };
 
                     trace!("Watching Directory");
 
-                    watcher.watch(directory.as_std_path(), notify::RecursiveMode::Recursive)?;
+                    watcher.watch(directory.as_std_path(), mode)?;
 
                     trace!("Returning Watcher");
 
@@ -85,66 +157,24 @@ impl Watcher for SimpleWatcher {
                         let mut watcher = {
                             let channel = self.channel.clone();
                             let cwd = cwd.clone();
-                            notify::recommended_watcher(
-                                move |file: Result<notify::Event, notify::Error>| {
+                            let hash_cache = HashCache::shared();
+                            self.create_watcher(
+                                move |event: Result<notify::Event, notify::Error>| {
                                     trace!("Got Asset Event");
-                                    if let Ok(file) = file {
-                                        let files = file
-                                            .paths
-                                            .iter()
-                                            .filter_map(|p| {
-                                                Utf8PathBuf::try_from(p.clone())
-                                                    .map_err(WatcherError::from)
-                                                    .and_then(|path| {
-                                                        if path.is_file() {
-                                                            Ok(path)
-                                                        } else {
-                                                            Err(WatcherError::OtherError(format!(
-                                                                "Path is not a file {path}"
-                                                            )))
-                                                        }
-                                                    })
-                                                    .and_then(|path| {
-                                                        std::fs::read(&path)
-                                                            .map_err(WatcherError::from)
-                                                            .and_then(|file| {
-                                                                let name =
-                                                                    match path.file_name() {
-                                                                        Some(n) => n.to_string(),
-                                                                        None => return Err(
-                                                                            WatcherError::NotAFile(
-                                                                                path.clone(),
-                                                                            ),
-                                                                        ),
-                                                                    };
-                                                                let hash = blake3::hash(&file);
-                                                                eprintln!("Got {path} Hash: {hash}, {:?} ", std::str::from_utf8(&file));
-                                                                let relative_path = path
-                                                                    .strip_prefix(&cwd)
-                                                                    .map(|p| p.to_owned())
-                                                                    .unwrap_or_else(|_| {
-                                                                        path.clone()
-                                                                    });
-                                                                let record = HashedFileRecord::new(
-                                                                    relative_path,
-                                                                    path.clone(),
-                                                                    name,
-                                                                    hash.as_bytes().to_owned(),
-                                                                );
-                                                                Ok(record)
-                                                            })
-                                                    })
-                                                    .ok()
-                                            })
-                                            .collect::<Vec<_>>();
-                                        trace!("Asset Change Records: {files:?}");
-                                        for file in files.into_iter() {
-                                            let _ = channel.send(
-                                                BuilderIncomingMessages::AssetChanged(
-                                                    file,
-                                                ),
-                                            );
+                                    let event = match event {
+                                        Ok(event) => event,
+                                        Err(e) => {
+                                            debug!("Asset Watch Error - {e}");
+                                            return;
                                         }
+                                    };
+                                    let messages = asset_event_messages(event, &cwd, &hash_cache);
+                                    trace!("Asset Change Messages: {messages:?}");
+                                    if let Err(e) = hash_cache.persist() {
+                                        debug!("Couldn't persist hash cache - {e}");
+                                    }
+                                    for message in messages.into_iter() {
+                                        let _ = channel.send(message);
                                     }
                                 },
                             )?
@@ -155,9 +185,16 @@ impl Watcher for SimpleWatcher {
 
                         trace!("Returning Watcher");
 
-                        if let Ok(initial) = gather_directory_content(directory, &cwd) {
+                        let hash_cache = HashCache::shared();
+                        if let Ok(initial) = gather_directory_content(directory, &cwd, &hash_cache)
+                        {
+                            if let Err(e) = hash_cache.persist() {
+                                debug!("Couldn't persist hash cache - {e}");
+                            }
                             for file in initial {
-                                let _ = self.channel.send(BuilderIncomingMessages::AssetChanged(file));
+                                let _ = self
+                                    .channel
+                                    .send(BuilderIncomingMessages::AssetChanged(file));
                             }
                         }
 
@@ -173,9 +210,93 @@ impl Watcher for SimpleWatcher {
     }
 }
 
+/// Turns an asset event into the messages describing it - paths that no longer exist are reported as removed,
+/// and renames that the platform reports with both paths are kept together.
+fn asset_event_messages(
+    event: notify::Event,
+    cwd: &Utf8Path,
+    hash_cache: &HashCache,
+) -> Vec<BuilderIncomingMessages> {
+    let paths = event
+        .paths
+        .into_iter()
+        .filter_map(|path| Utf8PathBuf::from_path_buf(path).ok())
+        .collect::<Vec<_>>();
+
+    if let (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) =
+        (&event.kind, paths.as_slice())
+    {
+        if to.is_file() {
+            match hash_asset(to, cwd, hash_cache) {
+                Ok(record) => {
+                    return vec![BuilderIncomingMessages::AssetRenamed {
+                        from: relative_asset_path(from, cwd),
+                        to: record,
+                    }]
+                }
+                Err(e) => debug!("Couldn't hash renamed asset {to} - {e}"),
+            }
+        }
+    }
+
+    let gather_directories = matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_))
+    );
+
+    let mut messages = vec![];
+    for path in paths {
+        if path.is_file() {
+            match hash_asset(&path, cwd, hash_cache) {
+                Ok(record) => messages.push(BuilderIncomingMessages::AssetChanged(record)),
+                Err(e) => debug!("Couldn't hash asset {path} - {e}"),
+            }
+        } else if path.is_dir() {
+            if gather_directories {
+                if let Ok(records) = gather_directory_content(path, cwd, hash_cache) {
+                    messages.extend(
+                        records
+                            .into_iter()
+                            .map(BuilderIncomingMessages::AssetChanged),
+                    );
+                }
+            }
+        } else if !path.exists() {
+            messages.push(BuilderIncomingMessages::AssetRemoved(relative_asset_path(
+                &path, cwd,
+            )));
+        }
+    }
+    messages
+}
+
+fn relative_asset_path(path: &Utf8Path, cwd: &Utf8Path) -> Utf8PathBuf {
+    path.strip_prefix(cwd)
+        .map(|p| p.to_owned())
+        .unwrap_or_else(|_| path.to_owned())
+}
+
+fn hash_asset(
+    path: &Utf8Path,
+    cwd: &Utf8Path,
+    hash_cache: &HashCache,
+) -> Result<HashedFileRecord, WatcherError> {
+    let hash = hash_cache.hash_file(path)?;
+    let Some(name) = path.file_name() else {
+        return Err(WatcherError::NotAFile(path.to_owned()));
+    };
+    Ok(HashedFileRecord::new(
+        relative_asset_path(path, cwd),
+        path,
+        name,
+        hash,
+    ))
+}
+
 fn gather_directory_content(
     dir: Utf8PathBuf,
     cwd: &Utf8Path,
+    hash_cache: &HashCache,
 ) -> Result<Vec<HashedFileRecord>, std::io::Error> {
     let read = dir.read_dir()?;
     let result = read
@@ -197,28 +318,10 @@ fn gather_directory_content(
         })
         .filter_map(|(path, is_dir)| {
             if is_dir {
-                return gather_directory_content(path, cwd).ok();
+                return gather_directory_content(path, cwd, hash_cache).ok();
             }
-            std::fs::read(&path)
-                .map_err(WatcherError::from)
-                .and_then(|file| {
-                    let name = match path.file_name() {
-                        Some(n) => n.to_string(),
-                        None => return Err(WatcherError::NotAFile(path.clone())),
-                    };
-                    let hash = blake3::hash(&file);
-                    let relative_path = path
-                        .strip_prefix(cwd)
-                        .map(|p| p.to_owned())
-                        .unwrap_or_else(|_| path.clone());
-                    let record = HashedFileRecord::new(
-                        relative_path,
-                        path.clone(),
-                        name,
-                        hash.as_bytes().to_owned(),
-                    );
-                    Ok(vec![record])
-                })
+            hash_asset(&path, cwd, hash_cache)
+                .map(|record| vec![record])
                 .ok()
         })
         .flatten();
@@ -228,8 +331,6 @@ fn gather_directory_content(
 
 #[cfg(test)]
 mod test {
-    use std::time::Duration;
-
     use super::*;
     use test_temp_dir::test_temp_dir;
     use tokio::fs::*;
@@ -257,7 +358,7 @@ mod test {
 
         assert!(matches!(result, TryRecvError::Empty));
 
-        let _ = File::create(dir.as_path_untracked().join("test.txt"))
+        let _ = File::create(dir.as_path_untracked().join("test.rs"))
             .await
             .expect("Couldn't create file");
 
@@ -266,7 +367,106 @@ mod test {
             .expect("Didn't recieve watcher message on time")
             .expect("Didn't recieve watcher message");
 
-        assert!(matches!(result, BuilderIncomingMessages::CodeChanged));
+        let BuilderIncomingMessages::CodeChanged(paths) = result else {
+            panic!("Got Message that isn't Code Changed");
+        };
+        assert!(paths.iter().any(|path| path.file_name() == Some("test.rs")));
+    }
+
+    #[tokio::test]
+    async fn watched_files_survive_being_replaced() {
+        let dir = test_temp_dir!();
+        let root = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        write(root.join("Cargo.toml"), "[package]")
+            .await
+            .expect("Couldn't write manifest");
+
+        let watcher = SimpleWatcher::default();
+
+        let mut rx = watcher.channel.subscribe();
+
+        watcher
+            .watch_code_directories(&[root.join("Cargo.toml")])
+            .expect("Couldn't set up watcher on manifest");
+
+        for _ in 0..2 {
+            write(root.join("Cargo.toml.tmp"), "[package]\n")
+                .await
+                .expect("Couldn't write replacement");
+            rename(root.join("Cargo.toml.tmp"), root.join("Cargo.toml"))
+                .await
+                .expect("Couldn't replace manifest");
+
+            let result = timeout(Duration::from_millis(100), async {
+                loop {
+                    if let BuilderIncomingMessages::ManifestChanged = rx.recv().await? {
+                        return Ok::<_, broadcast::error::RecvError>(());
+                    }
+                }
+            })
+            .await
+            .expect("Didn't recieve manifest change on time");
+            assert!(result.is_ok());
+            while rx.try_recv().is_ok() {}
+        }
+    }
+
+    #[tokio::test]
+    async fn polling_watcher_can_see_changes_in_a_code_directory() {
+        let dir = test_temp_dir!();
+
+        let watcher =
+            SimpleWatcher::default().with_backend(WatchBackend::Poll(Duration::from_millis(20)));
+
+        let mut rx = watcher.channel.subscribe();
+
+        watcher
+            .watch_code_directories(&[Utf8PathBuf::from_path_buf(
+                dir.as_path_untracked().to_path_buf(),
+            )
+            .unwrap()])
+            .expect("Couldn't set up watcher on temporary directory");
+
+        let _ = File::create(dir.as_path_untracked().join("test.rs"))
+            .await
+            .expect("Couldn't create file");
+
+        let result = timeout(Duration::from_millis(500), rx.recv())
+            .await
+            .expect("Didn't recieve watcher message on time")
+            .expect("Didn't recieve watcher message");
+
+        let BuilderIncomingMessages::CodeChanged(paths) = result else {
+            panic!("Got Message that isn't Code Changed");
+        };
+        assert!(paths.iter().any(|path| path.file_name() == Some("test.rs")));
+    }
+
+    #[tokio::test]
+    async fn watcher_ignores_files_outside_the_code_filter() {
+        let dir = test_temp_dir!();
+
+        let watcher = SimpleWatcher::default();
+
+        let mut rx = watcher.channel.subscribe();
+
+        watcher
+            .watch_code_directories(&[Utf8PathBuf::from_path_buf(
+                dir.as_path_untracked().to_path_buf(),
+            )
+            .unwrap()])
+            .expect("Couldn't set up watcher on temporary directory");
+
+        let _ = File::create(dir.as_path_untracked().join("notes.txt"))
+            .await
+            .expect("Couldn't create file");
+        let _ = File::create(dir.as_path_untracked().join(".test.rs.swp"))
+            .await
+            .expect("Couldn't create file");
+
+        let result = timeout(Duration::from_millis(50), rx.recv()).await;
+
+        assert!(result.is_err(), "Unexpected message - {result:?}");
     }
 
     #[tokio::test]
@@ -350,4 +550,59 @@ mod test {
 
         assert!(hash != record.hash);
     }
+
+    #[tokio::test]
+    async fn watcher_reports_removed_and_renamed_assets() {
+        let dir = test_temp_dir!();
+        let root = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+
+        write(root.join("old.txt"), "something")
+            .await
+            .expect("Couldn't write file");
+        write(root.join("deleted.txt"), "something else")
+            .await
+            .expect("Couldn't write file");
+
+        let watcher = SimpleWatcher::default();
+
+        let mut rx = watcher.channel.subscribe();
+        watcher
+            .watch_asset_directories(std::slice::from_ref(&root))
+            .expect("Couldn't set up watcher on temporary directory");
+        while rx.try_recv().is_ok() {
+            eprintln!("Purging initial asset messages");
+        }
+
+        remove_file(root.join("deleted.txt"))
+            .await
+            .expect("Couldn't remove file");
+
+        let result = timeout(Duration::from_millis(100), rx.recv())
+            .await
+            .expect("Didn't recieve removal message on time")
+            .expect("Didn't recieve removal message");
+
+        let BuilderIncomingMessages::AssetRemoved(path) = result else {
+            panic!("Got Message that isn't Asset Removed - {result:?}");
+        };
+        assert_eq!(path.file_name(), Some("deleted.txt"));
+
+        rename(root.join("old.txt"), root.join("new.txt"))
+            .await
+            .expect("Couldn't rename file");
+
+        let renamed = timeout(Duration::from_millis(100), async {
+            loop {
+                if let BuilderIncomingMessages::AssetRenamed { from, to } = rx.recv().await? {
+                    return Ok::<_, broadcast::error::RecvError>((from, to));
+                }
+            }
+        })
+        .await
+        .expect("Didn't recieve rename message on time")
+        .expect("Didn't recieve rename message");
+
+        assert_eq!(renamed.0.file_name(), Some("old.txt"));
+        assert_eq!(renamed.1.name, "new.txt");
+    }
 }
@@ -1,12 +1,16 @@
-use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Arc,
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use camino::{FromPathBufError, Utf8PathBuf};
 
 use dashmap::DashMap;
-use dexterous_developer_types::{BuilderTypes, Target};
+use dexterous_developer_types::{BuildDiagnostic, BuildTimings, BuilderTypes, Target};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::sync::Mutex;
@@ -32,6 +36,37 @@ pub trait Builder: 'static + Send + Sync {
     fn root_lib_name(&self) -> Option<String>;
     fn get_code_subscriptions(&self) -> Vec<Utf8PathBuf>;
     fn get_asset_subscriptions(&self) -> Vec<Utf8PathBuf>;
+    fn runtime_environment(&self) -> HashMap<String, String> {
+        HashMap::new()
+    }
+    fn use_build_queue(&self, _queue: Arc<dyn BuildQueue>) {}
+}
+
+pub trait BuildQueue: 'static + Send + Sync {
+    fn enqueue(&self, target: Target) -> QueuedBuild;
+}
+
+pub struct QueuedBuild {
+    /// 1-based position among the builds still waiting, updated as the queue moves
+    pub position: tokio::sync::watch::Receiver<usize>,
+    pub permit: tokio::sync::oneshot::Receiver<BuildPermit>,
+}
+
+/// Held for the duration of a build - the queue is notified once it is dropped.
+pub struct BuildPermit(Option<Box<dyn FnOnce() + Send + Sync>>);
+
+impl BuildPermit {
+    pub fn new(release: impl FnOnce() + Send + Sync + 'static) -> Self {
+        Self(Some(Box::new(release)))
+    }
+}
+
+impl Drop for BuildPermit {
+    fn drop(&mut self) {
+        if let Some(release) = self.0.take() {
+            release();
+        }
+    }
 }
 
 pub trait Watcher: 'static + Send + Sync {
@@ -54,19 +89,35 @@ pub enum WatcherError {
     Utf8PathBufError(#[from] FromPathBufError),
     #[error("Path is not a file {0}")]
     NotAFile(Utf8PathBuf),
+    #[error("Invalid Watch Glob {0}")]
+    GlobError(#[from] globset::Error),
 }
 
 #[derive(Debug, Clone)]
 pub enum BuilderIncomingMessages {
     RequestBuild(Target),
-    CodeChanged,
+    CodeChanged(Vec<Utf8PathBuf>),
+    ManifestChanged,
     AssetChanged(HashedFileRecord),
+    AssetRemoved(Utf8PathBuf),
+    AssetRenamed {
+        from: Utf8PathBuf,
+        to: HashedFileRecord,
+    },
+    ReferencedBuilds {
+        target: Target,
+        builds: HashSet<u32>,
+    },
+    PauseBuilds(Target),
+    ResumeBuilds(Target),
 }
 
 #[derive(Debug, Clone)]
 pub enum BuilderOutgoingMessages {
     Waiting,
     BuildStarted,
+    /// Code folders the builder discovered on its own, which should be added to the watcher
+    WatchCodeFolders(Vec<Utf8PathBuf>),
 }
 
 #[derive(Clone, Debug, Default)]
@@ -77,8 +128,14 @@ pub struct CurrentBuildState {
     pub most_recent_completed_build: Arc<AtomicU32>,
     pub most_recent_started_build: Arc<AtomicU32>,
     pub builder_type: BuilderTypes,
+    pub runtime_environment: HashMap<String, String>,
+    pub diagnostics: Arc<Mutex<Vec<BuildDiagnostic>>>,
+    pub timings: Arc<Mutex<VecDeque<(u32, BuildTimings)>>>,
+    completed_at: Arc<DashMap<u32, Instant>>,
 }
 
+pub const BUILD_TIMING_HISTORY: usize = 20;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HashedFileRecord {
     pub relative_path: Utf8PathBuf,
@@ -114,12 +171,34 @@ pub enum BuildOutputMessages {
         root_library: String,
     },
     AssetUpdated(HashedFileRecord),
+    AssetRemoved(Utf8PathBuf),
+    AssetRenamed {
+        from: Utf8PathBuf,
+        to: HashedFileRecord,
+    },
+    CompilerDiagnostic {
+        id: u32,
+        diagnostic: BuildDiagnostic,
+    },
+    BuildTimings {
+        id: u32,
+        timings: BuildTimings,
+    },
+    BuildQueued {
+        id: u32,
+        position: usize,
+    },
     FailedBuild(String),
+    CancelledBuild(u32),
     KeepAlive,
 }
 
 impl CurrentBuildState {
-    pub fn new(root_library: Option<String>, builder_type: BuilderTypes) -> Self {
+    pub fn new(
+        root_library: Option<String>,
+        builder_type: BuilderTypes,
+        runtime_environment: HashMap<String, String>,
+    ) -> Self {
         Self {
             root_library: Arc::new(Mutex::new(root_library)),
             libraries: Default::default(),
@@ -127,18 +206,50 @@ impl CurrentBuildState {
             most_recent_completed_build: Arc::new(AtomicU32::new(0)),
             most_recent_started_build: Arc::new(AtomicU32::new(0)),
             builder_type,
+            runtime_environment,
+            diagnostics: Default::default(),
+            timings: Default::default(),
+            completed_at: Default::default(),
         }
     }
 
+    /// Records a runner loading a build in its broadcast timing, returning how long it took to get there after completing
+    pub async fn build_loaded(&self, id: u32) -> Option<Duration> {
+        let elapsed = self.completed_at.get(&id)?.elapsed();
+        let mut history = self.timings.lock().await;
+        if let Some((_, timings)) = history.iter_mut().find(|(build, _)| *build == id) {
+            timings.broadcast = timings.broadcast.max(elapsed);
+        }
+        Some(elapsed)
+    }
+
     pub async fn update(&self, msg: BuildOutputMessages) -> &Self {
         match msg {
             BuildOutputMessages::AssetUpdated(record) => {
                 self.assets.insert(record.relative_path.clone(), record);
             }
+            BuildOutputMessages::AssetRemoved(relative_path) => {
+                // A removed directory takes everything inside it along
+                self.assets
+                    .retain(|path, _| !path.starts_with(&relative_path));
+            }
+            BuildOutputMessages::AssetRenamed { from, to } => {
+                self.assets.remove(&from);
+                self.assets.insert(to.relative_path.clone(), to);
+            }
             BuildOutputMessages::KeepAlive => {}
             BuildOutputMessages::StartedBuild(id) => {
-                self.most_recent_started_build
+                let previous = self
+                    .most_recent_started_build
                     .fetch_max(id, Ordering::SeqCst);
+                if id > previous {
+                    self.diagnostics.lock().await.clear();
+                }
+            }
+            BuildOutputMessages::CompilerDiagnostic { id, diagnostic } => {
+                if id >= self.most_recent_started_build.load(Ordering::SeqCst) {
+                    self.diagnostics.lock().await.push(diagnostic);
+                }
             }
             BuildOutputMessages::EndedBuild {
                 id,
@@ -150,10 +261,22 @@ impl CurrentBuildState {
                 }
                 self.most_recent_completed_build
                     .fetch_max(id, Ordering::SeqCst);
+                self.completed_at.insert(id, Instant::now());
+                self.completed_at
+                    .retain(|build, _| build + BUILD_TIMING_HISTORY as u32 > id);
                 let mut lock = self.root_library.lock().await;
                 let _ = lock.replace(root_library);
             }
+            BuildOutputMessages::BuildTimings { id, timings } => {
+                let mut history = self.timings.lock().await;
+                history.push_back((id, timings));
+                while history.len() > BUILD_TIMING_HISTORY {
+                    history.pop_front();
+                }
+            }
+            BuildOutputMessages::BuildQueued { .. } => {}
             BuildOutputMessages::FailedBuild(_) => {}
+            BuildOutputMessages::CancelledBuild(_) => {}
         }
         self
     }
@@ -161,11 +284,23 @@ impl CurrentBuildState {
 
 #[cfg(test)]
 mod test {
-    use std::sync::atomic::Ordering;
+    use std::{sync::atomic::Ordering, time::Duration};
 
     use camino::Utf8PathBuf;
-
-    use super::{BuildOutputMessages, CurrentBuildState, HashedFileRecord};
+    use dexterous_developer_types::{BuildDiagnostic, BuildTimings, DiagnosticLevel};
+
+    use super::{BuildOutputMessages, CurrentBuildState, HashedFileRecord, BUILD_TIMING_HISTORY};
+
+    fn diagnostic(message: &str) -> BuildDiagnostic {
+        BuildDiagnostic {
+            level: DiagnosticLevel::Error,
+            message: message.to_string(),
+            code: Some("E0308".to_string()),
+            spans: vec![],
+            children: vec![],
+            rendered: None,
+        }
+    }
 
     #[tokio::test]
     async fn current_build_state_can_update_asset_record() {
@@ -189,6 +324,76 @@ mod test {
         assert_eq!(record.local_path.as_str(), "/local/path");
     }
 
+    #[tokio::test]
+    async fn current_build_state_removes_deleted_assets() {
+        let state = CurrentBuildState::default();
+        for path in [
+            "assets/a.png",
+            "assets/sprites/b.png",
+            "assets/sprites/c.png",
+        ] {
+            let _ = state
+                .update(BuildOutputMessages::AssetUpdated(HashedFileRecord::new(
+                    path,
+                    path,
+                    "asset",
+                    Default::default(),
+                )))
+                .await;
+        }
+
+        let _ = state
+            .update(BuildOutputMessages::AssetRemoved(Utf8PathBuf::from(
+                "assets/a.png",
+            )))
+            .await;
+        assert!(!state
+            .assets
+            .contains_key(&Utf8PathBuf::from("assets/a.png")));
+        assert_eq!(state.assets.len(), 2);
+
+        let _ = state
+            .update(BuildOutputMessages::AssetRemoved(Utf8PathBuf::from(
+                "assets/sprites",
+            )))
+            .await;
+        assert!(state.assets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn current_build_state_moves_renamed_assets() {
+        let state = CurrentBuildState::default();
+        let _ = state
+            .update(BuildOutputMessages::AssetUpdated(HashedFileRecord::new(
+                "assets/old.png",
+                "/project/assets/old.png",
+                "old.png",
+                [1; 32],
+            )))
+            .await;
+
+        let _ = state
+            .update(BuildOutputMessages::AssetRenamed {
+                from: Utf8PathBuf::from("assets/old.png"),
+                to: HashedFileRecord::new(
+                    "assets/new.png",
+                    "/project/assets/new.png",
+                    "new.png",
+                    [1; 32],
+                ),
+            })
+            .await;
+
+        assert!(!state
+            .assets
+            .contains_key(&Utf8PathBuf::from("assets/old.png")));
+        let record = state
+            .assets
+            .get(&Utf8PathBuf::from("assets/new.png"))
+            .expect("Renamed asset wasn't added to current build state");
+        assert_eq!(record.name, "new.png");
+    }
+
     #[tokio::test]
     async fn starting_a_new_build_updates_current_state() {
         let state = CurrentBuildState::default();
@@ -265,4 +470,93 @@ mod test {
             .await;
         assert_eq!(state.most_recent_completed_build.load(Ordering::SeqCst), 2);
     }
+
+    #[tokio::test]
+    async fn diagnostics_are_kept_for_the_most_recent_build() {
+        let state = CurrentBuildState::default();
+
+        let _ = state.update(BuildOutputMessages::StartedBuild(1)).await;
+        let _ = state
+            .update(BuildOutputMessages::CompilerDiagnostic {
+                id: 1,
+                diagnostic: diagnostic("first"),
+            })
+            .await;
+
+        assert_eq!(state.diagnostics.lock().await.len(), 1);
+
+        let _ = state.update(BuildOutputMessages::StartedBuild(2)).await;
+        assert!(state.diagnostics.lock().await.is_empty());
+
+        let _ = state
+            .update(BuildOutputMessages::CompilerDiagnostic {
+                id: 1,
+                diagnostic: diagnostic("stale"),
+            })
+            .await;
+        let _ = state
+            .update(BuildOutputMessages::CompilerDiagnostic {
+                id: 2,
+                diagnostic: diagnostic("second"),
+            })
+            .await;
+
+        let diagnostics = state.diagnostics.lock().await;
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics.first().unwrap().message, "second");
+    }
+
+    #[tokio::test]
+    async fn build_timings_keep_a_rolling_history() {
+        let state = CurrentBuildState::default();
+
+        for id in 1..=(BUILD_TIMING_HISTORY as u32 + 5) {
+            let _ = state
+                .update(BuildOutputMessages::BuildTimings {
+                    id,
+                    timings: BuildTimings::default(),
+                })
+                .await;
+        }
+
+        let history = state.timings.lock().await;
+        assert_eq!(history.len(), BUILD_TIMING_HISTORY);
+        assert_eq!(history.front().map(|(id, _)| *id), Some(6));
+        assert_eq!(
+            history.back().map(|(id, _)| *id),
+            Some(BUILD_TIMING_HISTORY as u32 + 5)
+        );
+    }
+
+    #[tokio::test]
+    async fn broadcast_timings_cover_the_time_until_runners_load_the_build() {
+        let state = CurrentBuildState::default();
+        let _ = state
+            .update(BuildOutputMessages::EndedBuild {
+                id: 1,
+                libraries: vec![],
+                root_library: "root_lib".to_string(),
+            })
+            .await;
+        let _ = state
+            .update(BuildOutputMessages::BuildTimings {
+                id: 1,
+                timings: BuildTimings::default(),
+            })
+            .await;
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let first = state.build_loaded(1).await.expect("Build wasn't completed");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let slowest = state.build_loaded(1).await.expect("Build wasn't completed");
+
+        assert!(first >= Duration::from_millis(20));
+        assert!(slowest > first);
+        assert_eq!(state.build_loaded(2).await, None);
+        let history = state.timings.lock().await;
+        assert_eq!(
+            history.front().map(|(_, timings)| timings.broadcast),
+            Some(slowest)
+        );
+    }
 }
@@ -0,0 +1,303 @@
+use std::env;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use notify::{
+    event::{MetadataKind, ModifyKind},
+    EventKind,
+};
+use tracing::{debug, trace};
+
+/// Used when no include globs are configured - changes to anything else won't trigger a rebuild.
+pub const DEFAULT_CODE_WATCH_INCLUDE: &[&str] = &[
+    "**/*.rs",
+    "**/Cargo.toml",
+    "**/Cargo.lock",
+    "**/.cargo/config",
+    "**/.cargo/config.toml",
+];
+
+/// Always excluded, in addition to any configured exclude globs.
+pub const DEFAULT_CODE_WATCH_EXCLUDE: &[&str] = &[
+    "**/target/**",
+    "**/.git/**",
+    "**/*.swp",
+    "**/*.swx",
+    "**/*~",
+    "**/.#*",
+];
+
+#[derive(Debug, Clone)]
+pub struct CodeWatchFilter {
+    include: GlobSet,
+    exclude: GlobSet,
+    respect_gitignore: bool,
+}
+
+impl Default for CodeWatchFilter {
+    fn default() -> Self {
+        Self::new(&[], &[], true).expect("Default watch globs are valid")
+    }
+}
+
+impl CodeWatchFilter {
+    /// Globs are matched against paths relative to their watch root - see [`watch_root`].
+    pub fn new(
+        include: &[String],
+        exclude: &[String],
+        respect_gitignore: bool,
+    ) -> Result<Self, globset::Error> {
+        let include = if include.is_empty() {
+            glob_set(DEFAULT_CODE_WATCH_INCLUDE.iter().copied())?
+        } else {
+            glob_set(include.iter().map(|glob| glob.as_str()))?
+        };
+        let exclude = glob_set(
+            DEFAULT_CODE_WATCH_EXCLUDE
+                .iter()
+                .copied()
+                .chain(exclude.iter().map(|glob| glob.as_str())),
+        )?;
+        Ok(Self {
+            include,
+            exclude,
+            respect_gitignore,
+        })
+    }
+
+    pub fn gitignore_for(&self, directory: &Utf8Path) -> GitignoreRules {
+        if self.respect_gitignore {
+            GitignoreRules::load(directory)
+        } else {
+            GitignoreRules::default()
+        }
+    }
+
+    /// Only the part of the path within the root is matched, so directories above it can't be excluded by accident
+    pub fn matches(&self, path: &Utf8Path, root: &Utf8Path, gitignore: &GitignoreRules) -> bool {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+
+        if self.exclude.is_match(relative) {
+            trace!("{path} is excluded from code watching");
+            return false;
+        }
+        if !self.include.is_match(relative) {
+            trace!("{path} isn't included in code watching");
+            return false;
+        }
+        !gitignore.is_ignored(path)
+    }
+}
+
+/// The directory globs are matched relative to for a watched directory - the current directory if it's within it,
+/// or the watched directory itself otherwise. Relative directories are already relative to the current directory.
+pub fn watch_root(directory: &Utf8Path) -> Utf8PathBuf {
+    if directory.is_relative() {
+        return Utf8PathBuf::new();
+    }
+    env::current_dir()
+        .ok()
+        .and_then(|cwd| Utf8PathBuf::from_path_buf(cwd).ok())
+        .filter(|cwd| directory.starts_with(cwd))
+        .unwrap_or_else(|| directory.to_owned())
+}
+
+/// Only events that can change what gets compiled - access and metadata changes are ignored.
+/// Write time changes are kept, since they're all the polling backend reports for modified files.
+pub fn is_code_event(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Create(_) | EventKind::Remove(_) => true,
+        EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime)) => true,
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Modify(_) => true,
+        EventKind::Access(_) | EventKind::Any | EventKind::Other => false,
+    }
+}
+
+fn glob_set<'a>(globs: impl Iterator<Item = &'a str>) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+    builder.build()
+}
+
+/// The `.gitignore` files that apply to a watched directory - from its ancestors up to the repository root, and any nested within it.
+#[derive(Debug, Clone, Default)]
+pub struct GitignoreRules(Vec<Gitignore>);
+
+impl GitignoreRules {
+    pub fn load(directory: &Utf8Path) -> Self {
+        let mut files = vec![];
+        for dir in directory.ancestors() {
+            let file = dir.join(".gitignore");
+            if file.is_file() {
+                files.push(file);
+            }
+            if dir.join(".git").exists() {
+                break;
+            }
+        }
+
+        let nested = ignore::WalkBuilder::new(directory)
+            .hidden(false)
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name() == ".gitignore")
+            .filter_map(|entry| Utf8PathBuf::from_path_buf(entry.into_path()).ok());
+        for file in nested {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+
+        let mut rules = files
+            .into_iter()
+            .filter_map(|file| {
+                let root = file.parent()?;
+                let mut builder = GitignoreBuilder::new(root);
+                if let Some(e) = builder.add(&file) {
+                    debug!("Couldn't read {file} - {e}");
+                }
+                match builder.build() {
+                    Ok(gitignore) => Some(gitignore),
+                    Err(e) => {
+                        debug!("Couldn't parse {file} - {e}");
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        // Deeper files take precedence, so they are checked first
+        rules.sort_by_key(|gitignore| std::cmp::Reverse(gitignore.path().components().count()));
+        Self(rules)
+    }
+
+    pub fn is_ignored(&self, path: &Utf8Path) -> bool {
+        for gitignore in self.0.iter() {
+            if !path.starts_with(gitignore.path()) {
+                continue;
+            }
+            match gitignore.matched_path_or_any_parents(path, path.is_dir()) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, DataChange};
+    use test_temp_dir::test_temp_dir;
+
+    #[test]
+    fn default_filter_only_watches_rust_sources_and_manifests() {
+        let filter = CodeWatchFilter::default();
+        let rules = GitignoreRules::default();
+        let root = Utf8Path::new("/project");
+
+        assert!(filter.matches(Utf8Path::new("/project/src/lib.rs"), root, &rules));
+        assert!(filter.matches(Utf8Path::new("/project/Cargo.toml"), root, &rules));
+        assert!(!filter.matches(Utf8Path::new("/project/README.md"), root, &rules));
+        assert!(!filter.matches(Utf8Path::new("/project/src/.lib.rs.swp"), root, &rules));
+        assert!(!filter.matches(
+            Utf8Path::new("/project/target/debug/build/out.rs"),
+            root,
+            &rules
+        ));
+    }
+
+    #[test]
+    fn configured_globs_replace_the_default_includes() {
+        let filter = CodeWatchFilter::new(
+            &["**/*.wgsl".to_string()],
+            &["**/generated/**".to_string()],
+            true,
+        )
+        .expect("Couldn't create filter");
+        let rules = GitignoreRules::default();
+        let root = Utf8Path::new("/project");
+
+        assert!(filter.matches(Utf8Path::new("/project/shaders/light.wgsl"), root, &rules));
+        assert!(!filter.matches(Utf8Path::new("/project/src/lib.rs"), root, &rules));
+        assert!(!filter.matches(Utf8Path::new("/project/generated/light.wgsl"), root, &rules));
+    }
+
+    #[test]
+    fn projects_nested_under_a_target_directory_are_still_watched() {
+        let filter = CodeWatchFilter::default();
+        let rules = GitignoreRules::default();
+        let root = Utf8Path::new("/home/user/target/game");
+
+        assert!(filter.matches(
+            Utf8Path::new("/home/user/target/game/src/lib.rs"),
+            root,
+            &rules
+        ));
+        assert!(filter.matches(
+            Utf8Path::new("/home/user/target/game/Cargo.toml"),
+            root,
+            &rules
+        ));
+        assert!(!filter.matches(
+            Utf8Path::new("/home/user/target/game/target/debug/build/out.rs"),
+            root,
+            &rules
+        ));
+    }
+
+    #[test]
+    fn watch_roots_are_the_current_directory_when_possible() {
+        let cwd = Utf8PathBuf::from_path_buf(env::current_dir().unwrap()).unwrap();
+        let outside = Utf8Path::new("/elsewhere/helper/src");
+
+        assert_eq!(watch_root(&cwd.join("src")), cwd);
+        assert_eq!(watch_root(outside), outside);
+        assert_eq!(watch_root(Utf8Path::new("src")), Utf8PathBuf::new());
+    }
+
+    #[test]
+    fn gitignored_paths_are_skipped() {
+        let dir = test_temp_dir!();
+        let root = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::create_dir_all(root.join("src").join("nested")).unwrap();
+        std::fs::write(root.join(".gitignore"), "generated.rs\n").unwrap();
+        std::fs::write(
+            root.join("src").join("nested").join(".gitignore"),
+            "*.rs\n!keep.rs\n",
+        )
+        .unwrap();
+
+        let rules = GitignoreRules::load(&root.join("src"));
+
+        assert!(rules.is_ignored(&root.join("src").join("generated.rs")));
+        assert!(rules.is_ignored(&root.join("src").join("nested").join("skip.rs")));
+        assert!(!rules.is_ignored(&root.join("src").join("nested").join("keep.rs")));
+        assert!(!rules.is_ignored(&root.join("src").join("lib.rs")));
+    }
+
+    #[test]
+    fn access_and_metadata_events_are_ignored() {
+        assert!(is_code_event(&EventKind::Create(CreateKind::File)));
+        assert!(is_code_event(&EventKind::Modify(ModifyKind::Data(
+            DataChange::Content
+        ))));
+        assert!(is_code_event(&EventKind::Modify(ModifyKind::Metadata(
+            MetadataKind::WriteTime
+        ))));
+        assert!(!is_code_event(&EventKind::Access(AccessKind::Read)));
+        assert!(!is_code_event(&EventKind::Modify(ModifyKind::Metadata(
+            MetadataKind::Permissions
+        ))));
+    }
+}
@@ -0,0 +1,50 @@
+use dexterous_developer_types::{BuilderTypes, Target, TargetBuildSettings};
+
+use crate::{
+    default_builder::builder::DefaultBuilder,
+    types::{BuilderIncomingMessages, BuilderInitializer},
+};
+
+/// Builds hot reload patches with `cargo zigbuild`, using zig as the linker so a single host can cross compile for other targets.
+pub struct ZigBuilderInitializer {
+    target: Target,
+    settings: TargetBuildSettings,
+}
+
+impl ZigBuilderInitializer {
+    pub fn new(target: Target, settings: TargetBuildSettings) -> Self {
+        Self { target, settings }
+    }
+}
+
+impl BuilderInitializer for ZigBuilderInitializer {
+    type Inner = DefaultBuilder;
+
+    fn initialize_builder(
+        self,
+        channel: tokio::sync::broadcast::Sender<BuilderIncomingMessages>,
+    ) -> anyhow::Result<Self::Inner> {
+        let settings = TargetBuildSettings {
+            builder: BuilderTypes::Zig,
+            ..self.settings
+        };
+        DefaultBuilder::new(self.target, settings, channel)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::Builder;
+
+    #[tokio::test]
+    async fn zig_builders_report_their_builder_type() {
+        let (channel, _) = tokio::sync::broadcast::channel(10);
+        let builder = ZigBuilderInitializer::new(Target::LinuxArm, Default::default())
+            .initialize_builder(channel)
+            .expect("Couldn't initialize builder");
+
+        assert_eq!(builder.target(), Target::LinuxArm);
+        assert_eq!(builder.builder_type(), BuilderTypes::Zig);
+    }
+}
@@ -4,10 +4,15 @@ use camino::Utf8PathBuf;
 
 use clap::Parser;
 use dexterous_developer_builder::{
-    default_builder::builder::DefaultBuilderInitializer, simple_watcher::SimpleWatcher,
+    default_builder::builder::DefaultBuilderInitializer,
+    simple_watcher::{SimpleWatcher, WatchBackend},
+    watch_filter::CodeWatchFilter,
+    zig_builder::ZigBuilderInitializer,
 };
 use dexterous_developer_manager::{server::run_server, Manager};
-use dexterous_developer_types::{config::DexterousConfig, PackageOrExample, Target};
+use dexterous_developer_types::{
+    config::DexterousConfig, PackageOrExample, Target, WatcherBackend,
+};
 use tracing::{info, trace};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
@@ -22,6 +27,10 @@ struct Args {
     #[arg(short, long)]
     example: Option<String>,
 
+    /// Binary target to build
+    #[arg(long)]
+    bin: Option<String>,
+
     /// Features to include
     #[arg(short, long)]
     features: Vec<String>,
@@ -45,6 +54,7 @@ async fn main() -> anyhow::Result<()> {
     let Args {
         package,
         example,
+        bin,
         features,
         port,
         serve_only,
@@ -57,11 +67,12 @@ async fn main() -> anyhow::Result<()> {
         .await
         .expect("Couldn't load config");
 
-    let package_or_example = match (package, example) {
-        (None, None) => PackageOrExample::DefaulPackage,
-        (None, Some(example)) => PackageOrExample::Example(example),
-        (Some(package), None) => PackageOrExample::Package(package),
-        (Some(_), Some(_)) => panic!("Can only build either a package or an example, not both"),
+    let package_or_example = match (package, example, bin) {
+        (None, None, None) => PackageOrExample::DefaulPackage,
+        (None, Some(example), None) => PackageOrExample::Example(example),
+        (Some(package), None, None) => PackageOrExample::Package(package),
+        (None, None, Some(bin)) => PackageOrExample::Bin(bin),
+        _ => panic!("Can only build one of a package, an example or a bin"),
     };
 
     trace!("Setting up builders for {package_or_example:?}");
@@ -70,15 +81,40 @@ async fn main() -> anyhow::Result<()> {
         .generate_build_settings(Some(package_or_example.clone()), &features)
         .expect("Failed determine build settings");
 
+    let current_target = Target::current().expect("Can't find current target");
+    let profile_directory = builder_settings
+        .iter()
+        .find(|(target, _)| *target == current_target)
+        .map(|(_, settings)| settings.profile_directory().to_owned())
+        .unwrap_or_else(|| "debug".to_string());
+
     trace!("Setting up Manager");
 
-    let mut manager = Manager::new(Arc::new(SimpleWatcher::default()));
+    let code_filter = CodeWatchFilter::new(
+        &config.code_watch_include,
+        &config.code_watch_exclude,
+        config.respect_gitignore.unwrap_or(true),
+    )
+    .expect("Invalid code watch globs");
+    let watch_backend = match config.watcher.unwrap_or_default() {
+        WatcherBackend::Native => WatchBackend::Native,
+        WatcherBackend::Poll => WatchBackend::Poll(config.poll_interval()),
+    };
+    let mut manager = Manager::new(Arc::new(
+        SimpleWatcher::new(code_filter).with_backend(watch_backend),
+    ));
+    if let Some(max_concurrent_builds) = config.max_concurrent_builds {
+        manager = manager.with_max_concurrent_builds(max_concurrent_builds);
+    }
 
     for (target, build_settings) in builder_settings.into_iter() {
         manager = match build_settings.builder {
             dexterous_developer_types::BuilderTypes::Default => {
                 manager.add_builder(DefaultBuilderInitializer::new(target, build_settings))
             }
+            dexterous_developer_types::BuilderTypes::Zig => {
+                manager.add_builder(ZigBuilderInitializer::new(target, build_settings))
+            }
         }?;
     }
 
@@ -91,12 +127,14 @@ async fn main() -> anyhow::Result<()> {
         });
         {
             let mut cmd = tokio::process::Command::new("dexterous_developer_runner");
-            let target = Target::current().expect("Can't find current target");
+            let target = current_target;
             cmd.arg("--server").arg(format!("http://localhost:{port}"));
             cmd.arg("--working-directory")
                 .arg(&current_directory)
                 .arg("--library-path")
-                .arg(current_directory.join(format!("./target/hot-reload/{target}/{target}/debug")))
+                .arg(current_directory.join(format!(
+                    "./target/hot-reload/{target}/{target}/{profile_directory}"
+                )))
                 .arg("--in-workspace");
 
             let mut child = cmd.spawn().expect("Couldn't execute runner");
@@ -89,7 +89,6 @@ fn main() {
                     process::exit(1);
                 }
                 warn!("Couldn't find library path - adding it to the environment variables and restarting");
-                let executable = env::current_exe().expect("Couldn't get current executable");
                 let (env_var, env_val) = (if args.in_workspace {
                     let deps = library_path.join("deps");
                     let examples = library_path.join("examples");
@@ -98,28 +97,11 @@ fn main() {
                     add_to_dylib_path(&[&library_path])
                 })
                     .expect("Failed to add library path to dylib path");
-                let mut command = std::process::Command::new(executable);
-                command.arg("--working-directory")
-                .arg(working_directory)
-                .arg("--library-path")
-                .arg(library_path)
-                .arg("--server")
-                .arg(server.to_string())
-                .arg("--env-vars-preset");
-
-                if args.in_workspace {
-                    command.arg("--in-workspace");
-                }
-
-                let status = command
-                    .env(env_var, env_val)
-                    .status()
-                    .expect("Couldn't run with env variables");
-                if let Some(code) = status.code() {
-                    process::exit(code);
-                } else {
-                    process::exit(0);
-                }
+                restart(&working_directory, &library_path, &server, args.in_workspace, [(env_var, env_val)]);
+            }
+            dexterous_developer_dylib_runner::error::DylibRunnerError::RestartWithEnvironment(environment) => {
+                info!("Restarting with the runtime environment");
+                restart(&working_directory, &library_path, &server, args.in_workspace, environment);
             }
             e => {
                 error!("{e}");
@@ -129,3 +111,37 @@ fn main() {
     }
     process::exit(0);
 }
+
+/// Runs the runner again as a child process with additional environment variables, since they can't be set safely once threads are running
+fn restart<K: AsRef<std::ffi::OsStr>, V: AsRef<std::ffi::OsStr>>(
+    working_directory: &Utf8PathBuf,
+    library_path: &Utf8PathBuf,
+    server: &url::Url,
+    in_workspace: bool,
+    environment: impl IntoIterator<Item = (K, V)>,
+) -> ! {
+    let executable = env::current_exe().expect("Couldn't get current executable");
+    let mut command = std::process::Command::new(executable);
+    command
+        .arg("--working-directory")
+        .arg(working_directory)
+        .arg("--library-path")
+        .arg(library_path)
+        .arg("--server")
+        .arg(server.to_string())
+        .arg("--env-vars-preset");
+
+    if in_workspace {
+        command.arg("--in-workspace");
+    }
+
+    let status = command
+        .envs(environment)
+        .status()
+        .expect("Couldn't run with env variables");
+    if let Some(code) = status.code() {
+        process::exit(code);
+    } else {
+        process::exit(0);
+    }
+}
@@ -0,0 +1,474 @@
+use std::{collections::HashSet, sync::Arc, time::SystemTime};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use dashmap::DashMap;
+use reqwest::{
+    header::{ACCEPT_ENCODING, CONTENT_ENCODING, RANGE},
+    StatusCode,
+};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+use tracing::{trace, warn};
+
+use crate::error::DylibRunnerError;
+
+const DOWNLOAD_ATTEMPTS: usize = 3;
+const UNREFERENCED_BLOB_LIMIT: u64 = 256 * 1024 * 1024;
+
+/// Downloaded files, stored by their blake3 hash so they can be restored without a download
+/// whenever the server publishes the same content again - including after the runner restarts.
+#[derive(Debug, Clone)]
+pub struct BlobCache {
+    root: Utf8PathBuf,
+    downloads: Arc<DashMap<[u8; 32], Arc<Mutex<()>>>>,
+    unreferenced_limit: u64,
+}
+
+impl BlobCache {
+    pub fn new(root: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            downloads: Default::default(),
+            unreferenced_limit: UNREFERENCED_BLOB_LIMIT,
+        }
+    }
+
+    /// How many bytes of blobs that aren't part of the current build are kept around
+    pub fn with_unreferenced_limit(mut self, limit: u64) -> Self {
+        self.unreferenced_limit = limit;
+        self
+    }
+
+    fn blob_path(&self, hash: &[u8; 32]) -> Utf8PathBuf {
+        self.root
+            .join(blake3::Hash::from_bytes(*hash).to_hex().as_str())
+    }
+
+    /// Where an in-progress download is kept, so it can be resumed after a failure
+    fn partial_path(&self, hash: &[u8; 32], compressed: bool) -> Utf8PathBuf {
+        let blob = self.blob_path(hash);
+        if compressed {
+            blob.with_extension("zst.partial")
+        } else {
+            blob.with_extension("partial")
+        }
+    }
+
+    /// Places a cached blob at the destination, returning false if it isn't cached.
+    ///
+    /// The destination is only ever replaced by a rename, so nothing sees a partially written file.
+    pub async fn restore(&self, hash: &[u8; 32], destination: &Utf8Path) -> std::io::Result<bool> {
+        let blob = self.blob_path(hash);
+        let hash = *hash;
+        let destination = destination.to_owned();
+        tokio::task::spawn_blocking(move || restore_blob(&blob, &hash, &destination))
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?
+    }
+
+    /// Removes blobs that aren't part of the current build, except for the most recently used ones within the size limit
+    pub async fn prune(&self, current: HashSet<[u8; 32]>) -> std::io::Result<usize> {
+        let root = self.root.clone();
+        let limit = self.unreferenced_limit;
+        tokio::task::spawn_blocking(move || prune_blobs(&root, &current, limit))
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?
+    }
+
+    /// Downloads a blob from the server into the cache, resuming any partial download left behind
+    pub async fn fetch(&self, server: &url::Url, hash: &[u8; 32]) -> Result<(), DylibRunnerError> {
+        let lock = self.downloads.entry(*hash).or_default().clone();
+        let _guard = lock.lock().await;
+
+        if tokio::fs::try_exists(self.blob_path(hash))
+            .await
+            .unwrap_or(false)
+        {
+            return Ok(());
+        }
+        tokio::fs::create_dir_all(&self.root).await?;
+
+        let address = server
+            .join("blob/")?
+            .join(blake3::Hash::from_bytes(*hash).to_hex().as_str())?;
+        let client = reqwest::Client::new();
+        let mut attempt = 1;
+        loop {
+            match self.download(&client, &address, hash).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < DOWNLOAD_ATTEMPTS => {
+                    warn!("Download of {address} failed, retrying - {e}");
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn download(
+        &self,
+        client: &reqwest::Client,
+        address: &url::Url,
+        hash: &[u8; 32],
+    ) -> Result<(), DylibRunnerError> {
+        let mut existing = None;
+        for compressed in [true, false] {
+            let partial = self.partial_path(hash, compressed);
+            if let Ok(metadata) = tokio::fs::metadata(&partial).await {
+                if metadata.len() > 0 {
+                    existing = Some((compressed, metadata.len()));
+                    break;
+                }
+            }
+        }
+
+        let mut request = client.get(address.clone()).header(ACCEPT_ENCODING, "zstd");
+        if let Some((_, length)) = existing {
+            trace!("Resuming download of {address} from byte {length}");
+            request = request.header(RANGE, format!("bytes={length}-"));
+        }
+        let mut response = request.send().await?;
+
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            self.discard_partials(hash).await;
+            return Err(DylibRunnerError::ResumeFailed(address.clone()));
+        }
+        if let Err(e) = response.error_for_status_ref() {
+            return Err(e.into());
+        }
+
+        let compressed = match response.headers().get(CONTENT_ENCODING) {
+            None => false,
+            Some(encoding) if encoding == "zstd" => true,
+            Some(encoding) => {
+                return Err(DylibRunnerError::UnsupportedEncoding(format!(
+                    "{encoding:?}"
+                )))
+            }
+        };
+        let resuming = response.status() == StatusCode::PARTIAL_CONTENT;
+        if resuming
+            && existing.is_none_or(|(partial_compressed, _)| partial_compressed != compressed)
+        {
+            self.discard_partials(hash).await;
+            return Err(DylibRunnerError::ResumeFailed(address.clone()));
+        }
+
+        let partial = self.partial_path(hash, compressed);
+        let mut file = if resuming {
+            tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(&partial)
+                .await?
+        } else {
+            self.discard_partials(hash).await;
+            tokio::fs::File::create(&partial).await?
+        };
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        drop(file);
+        trace!("Downloaded {address}");
+
+        self.complete(hash, &partial, compressed).await
+    }
+
+    /// Moves a finished download into place, decompressing it if needed, as long as it has the expected hash
+    async fn complete(
+        &self,
+        hash: &[u8; 32],
+        partial: &Utf8Path,
+        compressed: bool,
+    ) -> Result<(), DylibRunnerError> {
+        let blob = self.blob_path(hash);
+        let decoded = temporary_path(&blob);
+        let source = partial.to_owned();
+        let destination = decoded.clone();
+        let result = tokio::task::spawn_blocking(move || -> std::io::Result<blake3::Hash> {
+            let mut input = std::fs::File::open(&source)?;
+            let mut output = std::fs::File::create(&destination)?;
+            if compressed {
+                zstd::stream::copy_decode(&mut input, &mut output)?;
+            } else {
+                std::io::copy(&mut input, &mut output)?;
+            }
+            output.sync_all()?;
+            drop(output);
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_reader(std::fs::File::open(&destination)?)?;
+            Ok(hasher.finalize())
+        })
+        .await
+        .map_err(|e| std::io::Error::other(e.to_string()))
+        .and_then(|result| result);
+
+        // A partial that can't be decoded, or has the wrong content, won't get any better by resuming it
+        let _ = tokio::fs::remove_file(partial).await;
+        let downloaded_hash = match result {
+            Ok(downloaded_hash) => downloaded_hash,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&decoded).await;
+                return Err(e.into());
+            }
+        };
+        if downloaded_hash.as_bytes() != hash {
+            let _ = tokio::fs::remove_file(&decoded).await;
+            return Err(DylibRunnerError::HashMismatch {
+                expected: blake3::Hash::from_bytes(*hash).to_hex().to_string(),
+                received: downloaded_hash.to_hex().to_string(),
+            });
+        }
+        tokio::fs::rename(&decoded, &blob).await?;
+        Ok(())
+    }
+
+    async fn discard_partials(&self, hash: &[u8; 32]) {
+        for compressed in [true, false] {
+            let _ = tokio::fs::remove_file(self.partial_path(hash, compressed)).await;
+        }
+    }
+}
+
+fn restore_blob(blob: &Utf8Path, hash: &[u8; 32], destination: &Utf8Path) -> std::io::Result<bool> {
+    let file = match std::fs::File::open(blob) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(&file)?;
+    if hasher.finalize().as_bytes() != hash {
+        warn!("Cached blob {blob} is corrupted, discarding it");
+        std::fs::remove_file(blob)?;
+        return Ok(false);
+    }
+    // Pruning goes by modification time, so this keeps recently used blobs around the longest
+    let _ = file.set_modified(SystemTime::now());
+    drop(file);
+
+    if let Some(dir) = destination.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let temporary = temporary_path(destination);
+    // Linking avoids keeping a second copy of the content, but only works within one file system
+    if std::fs::hard_link(blob, &temporary).is_err() {
+        if let Err(e) = std::fs::copy(blob, &temporary) {
+            let _ = std::fs::remove_file(&temporary);
+            return Err(e);
+        }
+    }
+    let result = std::fs::rename(&temporary, destination);
+    // Renaming onto another link to the same blob leaves the temporary link behind
+    let _ = std::fs::remove_file(&temporary);
+    result?;
+    trace!("Restored {destination} from the blob cache");
+    Ok(true)
+}
+
+fn prune_blobs(root: &Utf8Path, current: &HashSet<[u8; 32]>, limit: u64) -> std::io::Result<usize> {
+    let entries = match root.read_dir_utf8() {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut unreferenced = vec![];
+    for entry in entries {
+        let entry = entry?;
+        // Partial downloads and temporary files aren't named by a hash
+        let Ok(hash) = blake3::Hash::from_hex(entry.file_name()) else {
+            continue;
+        };
+        if current.contains(hash.as_bytes()) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        unreferenced.push((metadata.modified()?, metadata.len(), entry.into_path()));
+    }
+    unreferenced.sort_by(|(a, ..), (b, ..)| b.cmp(a));
+
+    let mut kept = 0;
+    let mut removed = 0;
+    for (_, size, path) in unreferenced {
+        if removed == 0 && kept + size <= limit {
+            kept += size;
+            continue;
+        }
+        trace!("Evicting {path} from the blob cache");
+        std::fs::remove_file(&path)?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// A unique sibling of the path, so it can be written in full and then renamed over the path
+fn temporary_path(path: &Utf8Path) -> Utf8PathBuf {
+    let name = path.file_name().unwrap_or_default();
+    path.with_file_name(format!(".{name}.{}.tmp", uuid::Uuid::new_v4()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Router};
+
+    use super::*;
+
+    fn test_cache() -> BlobCache {
+        BlobCache::new(
+            Utf8PathBuf::try_from(std::env::temp_dir())
+                .unwrap()
+                .join(format!("blobs-{}", uuid::Uuid::new_v4())),
+        )
+    }
+
+    /// Serves the content for every blob, after failing the given number of requests
+    async fn blob_server(content: &'static [u8], failures: usize) -> (url::Url, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/blob/:hash",
+                get(move |State(requests): State<Arc<AtomicUsize>>| async move {
+                    if requests.fetch_add(1, Ordering::SeqCst) < failures {
+                        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                    } else {
+                        content.into_response()
+                    }
+                }),
+            )
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (
+            url::Url::parse(&format!("http://127.0.0.1:{port}/")).unwrap(),
+            requests,
+        )
+    }
+
+    #[tokio::test]
+    async fn completing_a_download_with_the_wrong_content_is_rejected() {
+        let cache = test_cache();
+        tokio::fs::create_dir_all(&cache.root).await.unwrap();
+        let hash = *blake3::hash(b"expected").as_bytes();
+        let partial = cache.partial_path(&hash, false);
+        tokio::fs::write(&partial, b"received").await.unwrap();
+
+        let result = cache.complete(&hash, &partial, false).await;
+
+        assert!(matches!(result, Err(DylibRunnerError::HashMismatch { .. })));
+        assert!(!cache.blob_path(&hash).exists());
+        assert!(!partial.exists());
+        let _ = tokio::fs::remove_dir_all(&cache.root).await;
+    }
+
+    #[tokio::test]
+    async fn failed_downloads_are_retried() {
+        let cache = test_cache();
+        let hash = *blake3::hash(b"content").as_bytes();
+        let (server, requests) = blob_server(b"content", DOWNLOAD_ATTEMPTS - 1).await;
+
+        cache.fetch(&server, &hash).await.expect("Couldn't fetch");
+
+        assert_eq!(requests.load(Ordering::SeqCst), DOWNLOAD_ATTEMPTS);
+        let destination = cache.root.join("restored");
+        assert!(cache.restore(&hash, &destination).await.unwrap());
+        assert_eq!(tokio::fs::read(&destination).await.unwrap(), b"content");
+        let _ = tokio::fs::remove_dir_all(&cache.root).await;
+    }
+
+    #[tokio::test]
+    async fn downloads_give_up_after_the_last_attempt() {
+        let cache = test_cache();
+        let hash = *blake3::hash(b"expected").as_bytes();
+        let (server, requests) = blob_server(b"received", 0).await;
+
+        let result = cache.fetch(&server, &hash).await;
+
+        assert!(matches!(result, Err(DylibRunnerError::HashMismatch { .. })));
+        assert_eq!(requests.load(Ordering::SeqCst), DOWNLOAD_ATTEMPTS);
+        assert!(!cache.blob_path(&hash).exists());
+        let _ = tokio::fs::remove_dir_all(&cache.root).await;
+    }
+
+    #[tokio::test]
+    async fn corrupted_blobs_are_discarded_instead_of_restored() {
+        let cache = test_cache();
+        tokio::fs::create_dir_all(&cache.root).await.unwrap();
+        let hash = *blake3::hash(b"content").as_bytes();
+        tokio::fs::write(cache.blob_path(&hash), b"corrupted")
+            .await
+            .unwrap();
+        let destination = cache.root.join("restored");
+
+        let restored = cache.restore(&hash, &destination).await.unwrap();
+
+        assert!(!restored);
+        assert!(!cache.blob_path(&hash).exists());
+        assert!(!destination.exists());
+        let _ = tokio::fs::remove_dir_all(&cache.root).await;
+    }
+
+    #[tokio::test]
+    async fn restoring_links_the_blob_into_place() {
+        let cache = test_cache();
+        let hash = *blake3::hash(b"content").as_bytes();
+        let (server, _) = blob_server(b"content", 0).await;
+        cache.fetch(&server, &hash).await.expect("Couldn't fetch");
+        let destination = cache.root.join("nested").join("restored");
+
+        assert!(cache.restore(&hash, &destination).await.unwrap());
+        assert!(cache.restore(&hash, &destination).await.unwrap());
+
+        assert_eq!(tokio::fs::read(&destination).await.unwrap(), b"content");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(
+                destination.metadata().unwrap().ino(),
+                cache.blob_path(&hash).metadata().unwrap().ino()
+            );
+        }
+        let mut leftovers = cache.root.join("nested").read_dir_utf8().unwrap();
+        assert_eq!(leftovers.next().unwrap().unwrap().file_name(), "restored");
+        assert!(leftovers.next().is_none());
+        let _ = tokio::fs::remove_dir_all(&cache.root).await;
+    }
+
+    #[tokio::test]
+    async fn pruning_keeps_the_current_build_and_recently_used_blobs() {
+        let cache = test_cache().with_unreferenced_limit(16);
+        tokio::fs::create_dir_all(&cache.root).await.unwrap();
+        let mut blobs = vec![];
+        for (content, age) in [
+            ("current content that is large", 0),
+            ("recently used", 10),
+            ("used a while ago", 20),
+        ] {
+            let hash = *blake3::hash(content.as_bytes()).as_bytes();
+            let blob = cache.blob_path(&hash);
+            tokio::fs::write(&blob, content).await.unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&blob)
+                .unwrap()
+                .set_modified(SystemTime::now() - std::time::Duration::from_secs(age))
+                .unwrap();
+            blobs.push((hash, blob));
+        }
+        let partial = cache.partial_path(&blobs[2].0, true);
+        tokio::fs::write(&partial, b"partial").await.unwrap();
+
+        let removed = cache
+            .prune(HashSet::from([blobs[0].0]))
+            .await
+            .expect("Couldn't prune");
+
+        assert_eq!(removed, 1);
+        assert!(blobs[0].1.exists());
+        assert!(blobs[1].1.exists());
+        assert!(!blobs[2].1.exists());
+        assert!(partial.exists());
+        let _ = tokio::fs::remove_dir_all(&cache.root).await;
+    }
+}
@@ -1,9 +1,14 @@
+use std::collections::HashMap;
+
 use camino::Utf8PathBuf;
 use dexterous_developer_types::BuilderTypes;
 
 #[derive(Debug, Clone)]
 pub enum DylibRunnerMessage {
     ConnectionClosed,
+    RestartWithEnvironment {
+        environment: HashMap<String, String>,
+    },
     LoadRootLib {
         build_id: u32,
         local_path: Utf8PathBuf,
@@ -13,6 +18,15 @@ pub enum DylibRunnerMessage {
         local_path: Utf8PathBuf,
         name: String,
     },
+    AssetRemoved {
+        local_path: Utf8PathBuf,
+        name: String,
+    },
+    AssetRenamed {
+        previous_local_path: Utf8PathBuf,
+        local_path: Utf8PathBuf,
+        name: String,
+    },
     SerializedMessage {
         message: Vec<u8>,
     },
@@ -21,5 +35,6 @@ pub enum DylibRunnerMessage {
 #[derive(Debug, Clone)]
 pub enum DylibRunnerOutput {
     LoadedLib { build_id: u32 },
+    LoadedRoot { build_id: u32 },
     SerializedMessage { message: Vec<u8> },
 }
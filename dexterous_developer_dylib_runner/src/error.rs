@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use camino::Utf8PathBuf;
 use thiserror::Error;
 
@@ -20,7 +22,7 @@ pub enum DylibRunnerError {
     #[error("Library Directory does not exist - {0:?}")]
     LibraryDirectoryDoesntExist(Utf8PathBuf),
     #[error("WebSocket Error {0}")]
-    WebSocketError(#[from] tokio_tungstenite::tungstenite::Error),
+    WebSocketError(Box<tokio_tungstenite::tungstenite::Error>),
     #[error("RMP Parse Error {0}")]
     RmpParseError(#[from] rmp_serde::decode::Error),
     #[error("RMP Encoder Error {0}")]
@@ -39,4 +41,18 @@ pub enum DylibRunnerError {
     DownloadError(#[from] reqwest::Error),
     #[error("Couldn'y Determine Downloaded Asset Directory: {0}")]
     NoAssedDirectory(Utf8PathBuf),
+    #[error("Runner needs to restart with the runtime environment")]
+    RestartWithEnvironment(HashMap<String, String>),
+    #[error("Unsupported Download Encoding: {0}")]
+    UnsupportedEncoding(String),
+    #[error("Couldn't Resume Download of {0}")]
+    ResumeFailed(url::Url),
+    #[error("Downloaded Content Has Hash {received}, Expected {expected}")]
+    HashMismatch { expected: String, received: String },
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for DylibRunnerError {
+    fn from(value: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::WebSocketError(Box::new(value))
+    }
 }
@@ -1,5 +1,6 @@
 #![allow(non_snake_case)]
 
+pub mod blob_cache;
 pub mod dylib_runner_message;
 pub mod error;
 pub mod ffi;
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     sync::{
         atomic::{AtomicU32, Ordering},
         Arc,
@@ -7,21 +8,26 @@ use std::{
     time::Duration,
 };
 
-use camino::{Utf8Path, Utf8PathBuf};
-use dexterous_developer_types::{BuilderTypes, HotReloadMessage, Target};
-use futures_util::StreamExt;
-use tokio::{io::AsyncWriteExt, time::sleep};
-use tokio_tungstenite::connect_async;
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use dexterous_developer_types::{BuilderTypes, HotReloadMessage, HotReloadRunnerMessage, Target};
+use futures_util::{SinkExt, StreamExt};
+use tokio::time::sleep;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
 use tracing::{error, info, trace, warn};
 use url::Url;
 
-use crate::{dylib_runner_message::DylibRunnerMessage, error::DylibRunnerError};
+use crate::{
+    blob_cache::BlobCache,
+    dylib_runner_message::{DylibRunnerMessage, DylibRunnerOutput},
+    error::DylibRunnerError,
+};
 
 pub fn connect_to_server(
     working_directory: &Utf8Path,
     library_path: &Utf8Path,
     server: url::Url,
     tx: async_channel::Sender<DylibRunnerMessage>,
+    out_rx: async_channel::Receiver<DylibRunnerOutput>,
     in_workspace: bool,
 ) -> Result<JoinHandle<Result<(), DylibRunnerError>>, DylibRunnerError> {
     let current_target = Target::current().ok_or(DylibRunnerError::NoCurrentTarget)?;
@@ -51,7 +57,7 @@ pub fn connect_to_server(
     let address = address.clone();
     let library_path = library_path.to_owned();
     let working_directory = working_directory.to_owned();
-    let target = current_target;
+    let blob_cache = BlobCache::new(library_path.join(".blobs"));
 
     Ok(std::thread::spawn(move || {
         tokio::runtime::Builder::new_multi_thread()
@@ -63,8 +69,9 @@ pub fn connect_to_server(
                 let result = remote_connection(
                     address,
                     server,
-                    target,
+                    blob_cache,
                     tx.clone(),
+                    out_rx,
                     library_path,
                     working_directory,
                     in_workspace,
@@ -79,11 +86,13 @@ pub fn connect_to_server(
     }))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn remote_connection(
     address: Url,
     server: Url,
-    target: Target,
+    blob_cache: BlobCache,
     tx: async_channel::Sender<DylibRunnerMessage>,
+    out_rx: async_channel::Receiver<DylibRunnerOutput>,
     library_path: Utf8PathBuf,
     working_directory: Utf8PathBuf,
     in_workspace: bool,
@@ -97,7 +106,7 @@ pub(crate) async fn remote_connection(
 
     info!("Connected");
 
-    let (_, mut read) = ws_stream.split();
+    let (mut write, mut read) = ws_stream.split();
 
     let (download_tx, mut download_rx) = tokio::sync::mpsc::unbounded_channel::<DownloadResult>();
 
@@ -108,9 +117,28 @@ pub(crate) async fn remote_connection(
     let mut root_lib_name: Option<String> = None;
     let mut builder_type: Option<BuilderTypes> = None;
     let pending_downloads = Arc::new(AtomicU32::new(0));
+    // The blob cache holds on to these assets and the libraries of the current build when it's pruned
+    let mut current_assets: HashMap<Utf8PathBuf, [u8; 32]> = HashMap::new();
 
     loop {
         tokio::select! {
+            Ok(output) = out_rx.recv() => {
+                let message = match output {
+                    DylibRunnerOutput::LoadedLib { build_id } => {
+                        trace!("Reporting loaded build {build_id}");
+                        Some(HotReloadRunnerMessage::LoadedBuild(build_id))
+                    }
+                    DylibRunnerOutput::LoadedRoot { build_id } => {
+                        trace!("Reporting loaded root build {build_id}");
+                        Some(HotReloadRunnerMessage::LoadedRoot(build_id))
+                    }
+                    DylibRunnerOutput::SerializedMessage { .. } => None,
+                };
+                if let Some(message) = message {
+                    let msg = rmp_serde::to_vec(&message)?;
+                    write.send(Message::Binary(msg)).await?;
+                }
+            }
             Some(result) = download_rx.recv() => {
                 match result {
                         DownloadResult::Downloaded { name, local_path, is_asset } => {
@@ -156,6 +184,10 @@ pub(crate) async fn remote_connection(
                         }
                     }
                 }
+                DownloadResult::Renamed { name, previous_local_path, local_path } => {
+                    trace!("renamed asset {name}");
+                    let _ = tx.send(DylibRunnerMessage::AssetRenamed { previous_local_path, local_path, name }).await;
+                }
                 DownloadResult::DownloadNotFound { is_asset } => {
                     if !is_asset && pending_downloads.load(Ordering::SeqCst) == 0 {
                         trace!("all downloads completed");
@@ -202,6 +234,7 @@ pub(crate) async fn remote_connection(
                                 most_recent_started_build,
                                 most_recent_completed_build,
                                 builder_type: bt,
+                                environment,
                                 ..
                             } => {
                                 trace!(r#"Got Initial State:
@@ -210,25 +243,55 @@ pub(crate) async fn remote_connection(
                                 most_recent_completed_build: {most_recent_completed_build}"#);
 
                                 builder_type = Some(bt);
+                                // Changing the environment isn't safe once threads are running, so the runner has to start over with it instead
+                                if !environment_applied(&environment) {
+                                    info!("Runtime environment isn't applied yet, restarting with it");
+                                    let _ = tx.send(DylibRunnerMessage::RestartWithEnvironment { environment }).await;
+                                    return Ok(());
+                                }
                                 root_lib_name = initial_root_lib.as_ref().cloned();
+                                current_assets = assets.iter().cloned().collect();
+                                prune_blob_cache(&blob_cache, libraries.iter().map(|(_, hash)| *hash), &current_assets);
                                 for (path, hash) in libraries {
-                                    download_file(&server, target, &library_path, path, hash, pending_downloads.clone(), download_tx.clone(), false, in_workspace);
+                                    download_file(&server, &blob_cache, &library_path, path, hash, pending_downloads.clone(), download_tx.clone(), false, in_workspace);
                                 }
                                 for (path, hash) in assets {
-                                    download_file(&server, target, &working_directory, path, hash, pending_downloads.clone(), download_tx.clone(), true, in_workspace);
+                                    download_file(&server, &blob_cache, &working_directory, path, hash, pending_downloads.clone(), download_tx.clone(), true, in_workspace);
                                 }
                                 last_started_id = most_recent_started_build;
                                 last_completed_id = most_recent_completed_build;
 
                             },
                             HotReloadMessage::UpdatedAssets(path, hash) => {
-                                download_file(&server, target, &working_directory, path, hash, pending_downloads.clone(), download_tx.clone(), true, in_workspace);
+                                current_assets.insert(path.clone(), hash);
+                                download_file(&server, &blob_cache, &working_directory, path, hash, pending_downloads.clone(), download_tx.clone(), true, in_workspace);
                             },
-                            HotReloadMessage::BuildStarted(id) => {
-                                if id > last_started_id {
-                                    info!("build started: {id:?}");
-                                    last_started_id = id;
+                            HotReloadMessage::RemovedAsset(path) => {
+                                if !is_within(&path) {
+                                    warn!("Ignoring removal of {path}, since it's outside the working directory");
+                                    continue;
                                 }
+                                current_assets.retain(|asset, _| !asset.starts_with(&path));
+                                let local_path = working_directory.join(&path);
+                                if !in_workspace {
+                                    if let Err(e) = remove_local_asset(&local_path).await {
+                                        error!("Failed to remove {local_path} - {e}");
+                                    }
+                                }
+                                let _ = tx.send(DylibRunnerMessage::AssetRemoved { local_path, name: path.to_string() }).await;
+                            },
+                            HotReloadMessage::RenamedAsset { from, to, hash } => {
+                                if !is_within(&from) || !is_within(&to) {
+                                    warn!("Ignoring rename of {from} to {to}, since it's outside the working directory");
+                                    continue;
+                                }
+                                current_assets.remove(&from);
+                                current_assets.insert(to.clone(), hash);
+                                rename_asset(&server, &blob_cache, &working_directory, from, to, hash, download_tx.clone(), in_workspace);
+                            },
+                            HotReloadMessage::BuildStarted(id) if id > last_started_id => {
+                                info!("build started: {id:?}");
+                                last_started_id = id;
                             },
                             HotReloadMessage::BuildCompleted { id, libraries, root_library } => {
                                 info!("build completed: {id:?}");
@@ -238,10 +301,29 @@ pub(crate) async fn remote_connection(
                                 last_completed_id = id;
                                 root_lib_name = Some(root_library);
                                 root_lib_path = None;
+                                prune_blob_cache(&blob_cache, libraries.iter().map(|(_, hash, _)| *hash), &current_assets);
                                 for (path, hash, _) in &libraries {
-                                    download_file(&server,target,  &library_path, Utf8PathBuf::from(path), *hash, pending_downloads.clone(), download_tx.clone(), false, in_workspace);
+                                    download_file(&server, &blob_cache, &library_path, Utf8PathBuf::from(path), *hash, pending_downloads.clone(), download_tx.clone(), false, in_workspace);
                                 }
                             },
+                            HotReloadMessage::BuildDiagnostics { id, diagnostics } => {
+                                for diagnostic in diagnostics {
+                                    if diagnostic.level.is_error() {
+                                        error!("build {id}: {diagnostic}");
+                                    } else {
+                                        trace!("build {id}: {diagnostic}");
+                                    }
+                                }
+                            },
+                            HotReloadMessage::BuildTimings { id, timings } => {
+                                info!("build {id} timings: {timings}");
+                            },
+                            HotReloadMessage::BuildQueued { id, position } => {
+                                info!("build {id} queued at position {position}");
+                            },
+                            HotReloadMessage::BuildCancelled(id) => {
+                                info!("build {id} cancelled");
+                            },
                             _ => {}
                         }
                     }
@@ -265,15 +347,108 @@ enum DownloadResult {
         local_path: Utf8PathBuf,
         is_asset: bool,
     },
+    Renamed {
+        name: String,
+        previous_local_path: Utf8PathBuf,
+        local_path: Utf8PathBuf,
+    },
     DownloadNotFound {
         is_asset: bool,
     },
 }
 
+/// Paths from the server are joined onto the working directory, so anything that could escape it is rejected
+fn is_within(path: &Utf8Path) -> bool {
+    path.is_relative()
+        && path
+            .components()
+            .all(|component| matches!(component, Utf8Component::Normal(_) | Utf8Component::CurDir))
+}
+
+fn prune_blob_cache(
+    blob_cache: &BlobCache,
+    libraries: impl Iterator<Item = [u8; 32]>,
+    assets: &HashMap<Utf8PathBuf, [u8; 32]>,
+) {
+    let current = libraries.chain(assets.values().copied()).collect();
+    let blob_cache = blob_cache.clone();
+    tokio::spawn(async move {
+        match blob_cache.prune(current).await {
+            Ok(0) => {}
+            Ok(removed) => trace!("Evicted {removed} blobs from the cache"),
+            Err(e) => error!("Couldn't prune the blob cache - {e}"),
+        }
+    });
+}
+
+fn environment_applied(environment: &HashMap<String, String>) -> bool {
+    environment
+        .iter()
+        .all(|(key, value)| std::env::var(key).is_ok_and(|current| &current == value))
+}
+
+async fn remove_local_asset(local_path: &Utf8Path) -> std::io::Result<()> {
+    match tokio::fs::metadata(local_path).await {
+        Ok(metadata) if metadata.is_dir() => tokio::fs::remove_dir_all(local_path).await,
+        Ok(_) => tokio::fs::remove_file(local_path).await,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Moves the local copy where possible, and falls back to downloading the new path if it's missing or out of date
+#[allow(clippy::too_many_arguments)]
+fn rename_asset(
+    server: &url::Url,
+    blob_cache: &BlobCache,
+    base_path: &Utf8Path,
+    from: Utf8PathBuf,
+    to: Utf8PathBuf,
+    hash: [u8; 32],
+    tx: tokio::sync::mpsc::UnboundedSender<DownloadResult>,
+    in_workspace: bool,
+) {
+    let server = server.clone();
+    let blob_cache = blob_cache.clone();
+    let base_path = base_path.to_owned();
+    tokio::spawn(async move {
+        let previous_local_path = base_path.join(&from);
+        let local_path = base_path.join(&to);
+        let result = if in_workspace {
+            Ok(local_path)
+        } else {
+            if tokio::fs::try_exists(&previous_local_path)
+                .await
+                .unwrap_or(false)
+            {
+                if let Some(dir) = local_path.parent() {
+                    let _ = tokio::fs::create_dir_all(dir).await;
+                }
+                if let Err(e) = tokio::fs::rename(&previous_local_path, &local_path).await {
+                    trace!("Couldn't move {previous_local_path} - {e}");
+                }
+            }
+            execute_download(server, blob_cache, base_path, to.clone(), hash).await
+        };
+        match result {
+            Ok(local_path) => {
+                let _ = tx.send(DownloadResult::Renamed {
+                    name: to.to_string(),
+                    previous_local_path,
+                    local_path,
+                });
+            }
+            Err(e) => {
+                error!("Failed To Rename Asset {e:?}");
+            }
+        }
+    });
+}
+
 #[allow(clippy::too_many_arguments)]
 fn download_file(
     server: &url::Url,
-    target: Target,
+    blob_cache: &BlobCache,
     base_path: &Utf8Path,
     remote_path: Utf8PathBuf,
     hash: [u8; 32],
@@ -288,34 +463,27 @@ fn download_file(
             pending.fetch_add(1, Ordering::SeqCst);
         }
         let server = server.clone();
+        let blob_cache = blob_cache.clone();
         let base_path = base_path.to_owned();
         tokio::spawn(async move {
-            let result =
-                execute_download(server.clone(), target, base_path, remote_path.clone(), hash)
-                    .await;
+            let result = execute_download(
+                server.clone(),
+                blob_cache,
+                base_path,
+                remote_path.clone(),
+                hash,
+            )
+            .await;
             if !is_asset {
                 pending.fetch_sub(1, Ordering::SeqCst);
             }
             match result {
                 Ok(path) => {
-                    let name = remote_path.to_string();
-                    let mut wait = 0;
-                    while matches!(tokio::fs::try_exists(&path).await, Err(_) | Ok(false))
-                        && wait < 3
-                    {
-                        trace!("Waiting for file to exist");
-                        tokio::time::sleep(Duration::from_millis(500)).await;
-                        wait += 1;
-                    }
-                    if matches!(tokio::fs::try_exists(&path).await, Err(_) | Ok(false)) {
-                        error!("Failed to create file {path}");
-                    } else {
-                        let _ = tx.send(DownloadResult::Downloaded {
-                            name,
-                            local_path: path,
-                            is_asset,
-                        });
-                    }
+                    let _ = tx.send(DownloadResult::Downloaded {
+                        name: remote_path.to_string(),
+                        local_path: path,
+                        is_asset,
+                    });
                 }
                 Err(e) => {
                     error!("Failed To Download File {e:?}");
@@ -380,10 +548,9 @@ fn download_file(
     }
 }
 
-#[allow(clippy::too_many_arguments)]
 async fn execute_download(
     server: url::Url,
-    target: Target,
+    blob_cache: BlobCache,
     base_path: Utf8PathBuf,
     remote_path: Utf8PathBuf,
     hash: [u8; 32],
@@ -398,25 +565,15 @@ async fn execute_download(
         }
     }
 
-    let address = server
-        .join("files/")?
-        .join(&format!("{target}/"))?
-        .join(remote_path.as_str())?;
-    trace!("downloading {remote_path} from {address:?}");
-    let req = reqwest::get(address).await?.error_for_status()?;
-
-    let dir = local_path
-        .parent()
-        .ok_or_else(|| DylibRunnerError::NoAssedDirectory(local_path.clone()))?;
-    if !tokio::fs::try_exists(dir).await.unwrap_or(false) {
-        tokio::fs::create_dir_all(dir).await?;
+    if blob_cache.restore(&hash, &local_path).await? {
+        return Ok(local_path);
     }
 
-    let bytes = req.bytes().await?;
-
-    let mut file = tokio::fs::File::create(&local_path).await?;
-
-    file.write_all(&bytes).await?;
+    trace!("downloading {remote_path}");
+    blob_cache.fetch(&server, &hash).await?;
+    if !blob_cache.restore(&hash, &local_path).await? {
+        return Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
+    }
     trace!("downloaded {remote_path}");
 
     Ok(local_path)
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use camino::Utf8Path;
 
-use dexterous_developer_instance::{runner::HotReloadInfoBuilder, UpdatedAsset};
+use dexterous_developer_instance::{runner::HotReloadInfoBuilder, AssetChange, UpdatedAsset};
 use dexterous_developer_types::cargo_path_utils::dylib_path;
 use dylib_runner_message::DylibRunnerMessage;
 use error::DylibRunnerError;
@@ -38,23 +38,37 @@ pub fn run_reloadable_app(
     }
 
     let library_path = library_path.canonicalize_utf8()?;
+    remove_stale_backups(&library_path);
 
     let dylib_paths = dylib_path();
     if !dylib_paths.contains(&library_path.to_owned()) {
         return Err(DylibRunnerError::DylibPathsMissingLibraries);
     }
 
-    run_app(|tx, _| {
+    run_app(|tx, out_rx| {
         connect_to_server(
             working_directory,
             &library_path,
             server.clone(),
             tx,
+            out_rx,
             in_workspace,
         )
     })
 }
 
+fn remove_stale_backups(library_path: &Utf8Path) {
+    let Ok(dir) = library_path.read_dir_utf8() else {
+        return;
+    };
+    for entry in dir.flatten() {
+        if entry.path().extension() == Some("backup") {
+            trace!("Removing stale backup {}", entry.path());
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
 pub fn run_app<
     T: Fn(
         async_channel::Sender<DylibRunnerMessage>,
@@ -93,7 +107,7 @@ pub fn run_app<
                     builder_type: bt,
                 } => {
                     trace!("Loading Initial Root");
-                    library = Some(LibraryHolder::new(&local_path, false, bt)?);
+                    library = Some(LibraryHolder::new(&local_path)?);
                     path = Some(local_path);
                     id = Some(build_id);
                     builder_type = Some(bt);
@@ -103,6 +117,22 @@ pub fn run_app<
                     trace!("Asset: {name} {local_path}");
                     continue;
                 }
+                DylibRunnerMessage::AssetRemoved { local_path, name } => {
+                    trace!("Removed Asset: {name} {local_path}");
+                    continue;
+                }
+                DylibRunnerMessage::AssetRenamed {
+                    previous_local_path,
+                    local_path,
+                    name,
+                } => {
+                    trace!("Renamed Asset: {name} {previous_local_path} -> {local_path}");
+                    continue;
+                }
+                DylibRunnerMessage::RestartWithEnvironment { environment } => {
+                    let _ = handle.join().map_err(DylibRunnerError::JoinHandleFailed)?;
+                    return Err(DylibRunnerError::RestartWithEnvironment(environment));
+                }
                 DylibRunnerMessage::SerializedMessage { message: _ } => {}
             }
         }
@@ -141,7 +171,7 @@ pub fn run_app<
     .build();
 
     initial.varied_call("dexterous_developer_instance_set_hot_reload_info", info)?;
-    let _ = out_tx.send_blocking(DylibRunnerOutput::LoadedLib { build_id: id });
+    let _ = out_tx.send_blocking(DylibRunnerOutput::LoadedRoot { build_id: id });
     trace!("Calling Internal Main");
     initial.call("dexterous_developer_instance_main", &mut ())?;
 
@@ -179,26 +209,25 @@ fn update_loop(
                 }
             }
             DylibRunnerMessage::AssetUpdated { local_path, name } => {
-                if let Some(library) = ORIGINAL_LIBRARY.get() {
-                    trace!("Running Callback");
-                    let inner_local_path = c_slice::Box::from(
-                        local_path
-                            .to_string()
-                            .as_bytes()
-                            .iter()
-                            .copied()
-                            .collect::<Box<[u8]>>(),
-                    );
-                    let inner_name =
-                        c_slice::Box::from(name.as_bytes().iter().copied().collect::<Box<[u8]>>());
-                    let _ = library.varied_call(
-                        "update_asset_callback_internal",
-                        UpdatedAsset {
-                            inner_name,
-                            inner_local_path,
-                        },
-                    );
-                }
+                call_asset_callback(AssetChange::Updated, &name, &local_path, None);
+            }
+            DylibRunnerMessage::AssetRemoved { local_path, name } => {
+                call_asset_callback(AssetChange::Removed, &name, &local_path, None);
+            }
+            DylibRunnerMessage::AssetRenamed {
+                previous_local_path,
+                local_path,
+                name,
+            } => {
+                call_asset_callback(
+                    AssetChange::Renamed,
+                    &name,
+                    &local_path,
+                    Some(&previous_local_path),
+                );
+            }
+            DylibRunnerMessage::RestartWithEnvironment { .. } => {
+                warn!("Runtime environment changed, but the app is already running");
             }
             DylibRunnerMessage::SerializedMessage { message } => {
                 if let Some(library) = ORIGINAL_LIBRARY.get() {
@@ -212,3 +241,28 @@ fn update_loop(
         }
     }
 }
+
+fn call_asset_callback(
+    change: AssetChange,
+    name: &str,
+    local_path: &Utf8Path,
+    previous_local_path: Option<&Utf8Path>,
+) {
+    if let Some(library) = ORIGINAL_LIBRARY.get() {
+        trace!("Running Callback");
+        let to_slice = |value: &str| {
+            c_slice::Box::from(value.as_bytes().iter().copied().collect::<Box<[u8]>>())
+        };
+        let _ = library.varied_call(
+            "update_asset_callback_internal",
+            UpdatedAsset {
+                inner_name: to_slice(name),
+                inner_local_path: to_slice(local_path.as_str()),
+                inner_previous_local_path: to_slice(
+                    previous_local_path.map(|p| p.as_str()).unwrap_or_default(),
+                ),
+                change,
+            },
+        );
+    }
+}
@@ -15,12 +15,24 @@ pub struct HotReloadInfo {
     builder_type: safer_ffi::Vec<u8>,
 }
 
+#[derive_ReprC]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssetChange {
+    Updated,
+    Removed,
+    Renamed,
+}
+
 #[derive_ReprC]
 #[repr(C)]
 #[derive(Clone)]
 pub struct UpdatedAsset {
     pub inner_name: c_slice::Box<u8>,
     pub inner_local_path: c_slice::Box<u8>,
+    /// Empty unless the asset was renamed
+    pub inner_previous_local_path: c_slice::Box<u8>,
+    pub change: AssetChange,
 }
 
 #[derive_ReprC]
@@ -42,7 +54,7 @@ pub mod internal {
     use std::str::Utf8Error;
     use thiserror::Error;
 
-    use crate::{HotReloadInfo, UpdatedAsset};
+    use crate::{AssetChange, HotReloadInfo, UpdatedAsset};
 
     pub static HOT_RELOAD_INFO: OnceCell<HotReloadInfo> = OnceCell::new();
     pub static BUILDER_TYPE: OnceCell<BuilderTypes> = OnceCell::new();
@@ -63,14 +75,13 @@ pub mod internal {
         use std::sync::{Arc, RwLock};
 
         use camino::Utf8PathBuf;
-        use dexterous_developer_types::BuilderTypes;
         use safer_ffi::ffi_export;
         use serde::de::DeserializeOwned;
         use tracing::error;
 
         use crate::{library_holder::LibraryHolder, UpdatedAsset};
 
-        use super::{HotReloadAccessError, BUILDER_TYPE, HOT_RELOAD_INFO};
+        use super::{HotReloadAccessError, HOT_RELOAD_INFO};
 
         static CURRENT_LIBRARY: RwLock<Vec<LibraryHolder>> = RwLock::new(vec![]);
         static UPDATE_CALLBACK: RwLock<Option<Arc<dyn Fn() + Send + Sync>>> = RwLock::new(None);
@@ -83,8 +94,7 @@ pub mod internal {
         fn load_internal_library(path: safer_ffi::String) {
             println!("Called Internal Library");
             let path = Utf8PathBuf::from(path.to_string());
-            let builder_type = BUILDER_TYPE.get_or_init(|| BuilderTypes::Default);
-            let holder = match LibraryHolder::new(&path, false, *builder_type) {
+            let holder = match LibraryHolder::new(&path) {
                 Ok(holder) => holder,
                 Err(e) => {
                     eprintln!("Failed to load library {path} - {e}");
@@ -247,6 +257,15 @@ pub mod internal {
             let path = std::str::from_utf8(&self.inner_local_path)?;
             Ok(Utf8PathBuf::from(path))
         }
+
+        pub fn previous_local_path(&self) -> Result<Option<Utf8PathBuf>, Utf8Error> {
+            let path = std::str::from_utf8(&self.inner_previous_local_path)?;
+            Ok((!path.is_empty()).then(|| Utf8PathBuf::from(path)))
+        }
+
+        pub fn change(&self) -> AssetChange {
+            self.change
+        }
     }
 
     impl HotReloadInfo {
@@ -2,11 +2,10 @@ use camino::{Utf8Path, Utf8PathBuf};
 use dashmap::DashMap;
 use libloading::Library;
 use once_cell::sync::Lazy;
-use std::time::Duration;
 
-use dexterous_developer_types::{cargo_path_utils, BuilderTypes};
+use dexterous_developer_types::cargo_path_utils;
 use thiserror::Error;
-use tracing::{debug, error, trace};
+use tracing::{error, trace};
 use uuid::Uuid;
 
 static LIBRARIES: Lazy<DashMap<Uuid, LibraryHolderInner>> = Lazy::new(Default::default);
@@ -21,55 +20,17 @@ impl Drop for LibraryHolderInner {
 }
 
 impl LibraryHolderInner {
-    pub fn new(
-        path: &Utf8Path,
-        use_original: bool,
-        builder_type: BuilderTypes,
-    ) -> Result<(Self, Uuid), LibraryError> {
+    /// Loads the library in place - local builds and downloads are both only renamed into place once they're complete.
+    pub fn new(path: &Utf8Path) -> Result<(Self, Uuid), LibraryError> {
         trace!("Loading {path:?}");
         let path = path.to_owned();
-
-        let default_library = match builder_type {
-            BuilderTypes::Default => true,
-        };
-
         let uuid = uuid::Uuid::new_v4();
-        let path = if default_library || use_original {
-            println!("Using Original");
-            path
-        } else {
-            println!("Copying To Temporary File");
-            let extension = path.extension();
-            let new_path = path.clone();
-            let mut new_path = new_path.with_file_name(uuid.to_string());
-            let mut archival_path = path.clone();
-            if let Some(extension) = extension {
-                new_path.set_extension(extension);
-                archival_path.set_extension(format!("{}.{uuid}.backup", extension));
-            }
-
-            await_file(10, &path);
-            println!("Finished Waiting for {path}");
-
-            std::fs::copy(&path, &archival_path)?;
-            println!("Copied {path} to {archival_path}");
-
-            std::fs::copy(&path, &new_path)?;
-            debug!("Copied file to {path} to {new_path}");
-            println!("Renamed {path} to {new_path}");
-
-            await_file(10, &new_path);
-            println!("validated {new_path}");
-            Utf8PathBuf::try_from(dunce::canonicalize(new_path)?)?
-        };
-
-        println!("Loading Library");
 
         // SAFETY: Here we are relying on libloading's safety processes for ensuring the Library we receive is properly set up. We expect that library to respect rust ownership semantics because we control it's compilation and know that it is built in rust as well, but the wrappers are unaware so they rely on unsafe.
         let library = unsafe { Library::new(path.clone()).map(From::from) };
         match library {
             Ok(lib) => {
-                println!("Loaded library");
+                trace!("Loaded library");
                 Ok((Self(Some(lib), path), uuid))
             }
             Err(err) => {
@@ -122,29 +83,12 @@ impl LibraryHolderInner {
     }
 }
 
-fn await_file(iterations: usize, path: &Utf8PathBuf) {
-    if path.exists() {
-        println!("Validated {path:?} Exists");
-        std::thread::sleep(Duration::from_secs_f32(2.0));
-        return;
-    }
-    if iterations > 0 {
-        debug!("{path:?} doesn't exist yet...");
-        std::thread::sleep(Duration::from_secs_f32(0.5));
-        await_file(iterations.saturating_sub(1), path);
-    }
-}
-
 #[derive(Clone)]
 pub struct LibraryHolder(Uuid, Utf8PathBuf);
 
 impl LibraryHolder {
-    pub fn new(
-        path: &Utf8Path,
-        use_original: bool,
-        builder_type: BuilderTypes,
-    ) -> Result<Self, LibraryError> {
-        let (inner, uuid) = LibraryHolderInner::new(path, use_original, builder_type)?;
+    pub fn new(path: &Utf8Path) -> Result<Self, LibraryError> {
+        let (inner, uuid) = LibraryHolderInner::new(path)?;
         let path = inner.1.clone();
         LIBRARIES.insert(uuid, inner);
         Ok(Self(uuid, path))
@@ -0,0 +1,204 @@
+use std::{collections::HashSet, io::Read};
+
+use axum::http::{header::ACCEPT_ENCODING, HeaderMap};
+use camino::{Utf8Path, Utf8PathBuf};
+use dexterous_developer_builder::hash_cache::HashCache;
+use tracing::trace;
+
+pub const COMPRESSED_FILE_PATH: &str = "./target/hot-reload/compressed";
+
+/// Whether the client listed zstd in its Accept-Encoding, without explicitly refusing it with q=0
+pub fn accepts_zstd(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut parts = encoding.split(';').map(str::trim);
+            let accepted = parts
+                .next()
+                .is_some_and(|name| name.eq_ignore_ascii_case("zstd"));
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.)
+            });
+            accepted && !refused
+        })
+}
+
+/// Compresses a file into the cache directory, keyed by the hash of its content.
+///
+/// The hash comes from the hash cache, so a compressed copy that already exists is reused without reading the file.
+/// Otherwise the file is compressed as a stream and hashed as it's read, so the copy is keyed by the content that
+/// was actually compressed. Keeping the copy for as long as the content stays the same keeps ranged requests
+/// against it consistent between attempts.
+pub fn compressed_copy(
+    source: &Utf8Path,
+    cache_dir: &Utf8Path,
+    hash_cache: &HashCache,
+) -> std::io::Result<(Utf8PathBuf, [u8; 32])> {
+    let hash = hash_cache.hash_file(source)?;
+    let compressed = compressed_path(cache_dir, &hash);
+    if compressed.exists() {
+        return Ok((compressed, hash));
+    }
+
+    std::fs::create_dir_all(cache_dir)?;
+    let partial = cache_dir.join(format!(".{}.partial", uuid::Uuid::new_v4()));
+    let mut reader = HashingReader {
+        inner: std::fs::File::open(source)?,
+        hasher: blake3::Hasher::new(),
+    };
+    let result = std::fs::File::create(&partial).and_then(|mut output| {
+        zstd::stream::copy_encode(&mut reader, &mut output, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+        output.sync_all()
+    });
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+
+    let hash = *reader.hasher.finalize().as_bytes();
+    let compressed = compressed_path(cache_dir, &hash);
+    if let Err(e) = std::fs::rename(&partial, &compressed) {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    Ok((compressed, hash))
+}
+
+/// Removes compressed copies whose content isn't published anymore, returning how many were removed
+pub fn prune_compressed(
+    cache_dir: &Utf8Path,
+    published: &HashSet<[u8; 32]>,
+) -> std::io::Result<usize> {
+    let entries = match cache_dir.read_dir_utf8() {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let path = entry?.into_path();
+        if path.extension() != Some("zst") {
+            continue;
+        }
+        let Some(hash) = path
+            .file_stem()
+            .and_then(|stem| blake3::Hash::from_hex(stem).ok())
+        else {
+            continue;
+        };
+        if !published.contains(hash.as_bytes()) {
+            trace!("Removing compressed copy {path}");
+            std::fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+fn compressed_path(cache_dir: &Utf8Path, hash: &[u8; 32]) -> Utf8PathBuf {
+    cache_dir.join(format!(
+        "{}.zst",
+        blake3::Hash::from_bytes(*hash).to_hex().as_str()
+    ))
+}
+
+/// Hashes everything read through it
+struct HashingReader<R> {
+    inner: R,
+    hasher: blake3::Hasher,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn zstd_is_negotiated_from_accept_encoding() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_zstd(&headers));
+
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, zstd"));
+        assert!(accepts_zstd(&headers));
+
+        headers.insert(
+            ACCEPT_ENCODING,
+            HeaderValue::from_static("gzip, ZSTD;q=0.5"),
+        );
+        assert!(accepts_zstd(&headers));
+
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("zstd;q=0, gzip"));
+        assert!(!accepts_zstd(&headers));
+    }
+
+    #[test]
+    fn compressed_copies_are_keyed_by_content() {
+        let dir = Utf8PathBuf::try_from(std::env::temp_dir())
+            .unwrap()
+            .join(format!("compressed-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("library.so");
+        std::fs::write(&source, "library content").unwrap();
+        let hash_cache = HashCache::default();
+
+        let (compressed, hash) =
+            compressed_copy(&source, &dir.join("cache"), &hash_cache).expect("Couldn't compress");
+
+        assert_eq!(hash, *blake3::hash(b"library content").as_bytes());
+        let decoded = zstd::stream::decode_all(std::fs::File::open(&compressed).unwrap()).unwrap();
+        assert_eq!(decoded, b"library content");
+
+        let (again, _) =
+            compressed_copy(&source, &dir.join("cache"), &hash_cache).expect("Couldn't compress");
+        assert_eq!(again, compressed);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn compressed_copies_that_arent_published_are_pruned() {
+        let dir = Utf8PathBuf::try_from(std::env::temp_dir())
+            .unwrap()
+            .join(format!("compressed-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = dir.join("cache");
+        let hash_cache = HashCache::default();
+
+        let mut copies = vec![];
+        for content in ["first", "second"] {
+            let source = dir.join(content);
+            std::fs::write(&source, content).unwrap();
+            copies.push(compressed_copy(&source, &cache, &hash_cache).expect("Couldn't compress"));
+        }
+        let (first, first_hash) = &copies[0];
+        let (second, _) = &copies[1];
+
+        let removed =
+            prune_compressed(&cache, &HashSet::from([*first_hash])).expect("Couldn't prune");
+
+        assert_eq!(removed, 1);
+        assert!(first.exists());
+        assert!(!second.exists());
+        assert_eq!(
+            prune_compressed(&dir.join("missing"), &HashSet::new()).expect("Couldn't prune"),
+            0
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
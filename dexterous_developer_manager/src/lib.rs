@@ -1,3 +1,5 @@
+pub mod compression;
 pub mod manager;
+pub mod scheduler;
 pub mod server;
 pub use manager::{Manager, ManagerError};
@@ -1,5 +1,6 @@
 use camino::{Utf8Path, Utf8PathBuf};
 use dashmap::DashMap;
+use dexterous_developer_builder::hash_cache::HashCache;
 use dexterous_developer_builder::types::{
     BuildOutputMessages, Builder, BuilderIncomingMessages, BuilderInitializer,
     BuilderOutgoingMessages, CurrentBuildState, Watcher,
@@ -13,6 +14,11 @@ use tokio::{
 };
 use tracing::{error, info, trace};
 
+use crate::{
+    compression::{prune_compressed, COMPRESSED_FILE_PATH},
+    scheduler::BuildScheduler,
+};
+
 #[derive(Clone)]
 
 pub struct Manager {
@@ -30,6 +36,9 @@ pub struct Manager {
     >,
     target_count: usize,
     watcher: Option<Arc<dyn Watcher>>,
+    loaded_builds: Arc<DashMap<Target, DashMap<uuid::Uuid, HashSet<u32>>>>,
+    scheduler: BuildScheduler,
+    subscribers: Arc<DashMap<Target, usize>>,
 }
 
 impl Default for Manager {
@@ -39,6 +48,9 @@ impl Default for Manager {
             targets: Default::default(),
             target_count: Default::default(),
             watcher: Default::default(),
+            loaded_builds: Default::default(),
+            scheduler: Default::default(),
+            subscribers: Default::default(),
         }
     }
 }
@@ -52,6 +64,8 @@ pub enum ManagerError {
     ReceiveError(#[from] tokio::sync::broadcast::error::RecvError),
     #[error("Requested File Isn't Available")]
     NoSuchFile(Utf8PathBuf),
+    #[error("No File Has The Requested Hash")]
+    NoSuchBlob,
 }
 
 impl Manager {
@@ -63,9 +77,17 @@ impl Manager {
             targets: Default::default(),
             watcher: Some(watcher),
             target_count: 0,
+            loaded_builds: Default::default(),
+            scheduler: Default::default(),
+            subscribers: Default::default(),
         }
     }
 
+    pub fn with_max_concurrent_builds(self, max_concurrent: usize) -> Self {
+        self.scheduler.set_max_concurrent(max_concurrent);
+        self
+    }
+
     pub fn get_watcher_channel(&self) -> broadcast::Sender<BuilderIncomingMessages> {
         self.watcher_channel.clone()
     }
@@ -76,15 +98,17 @@ impl Manager {
     ) -> anyhow::Result<Self> {
         let builder = initializer.initialize_builder(self.watcher_channel.clone())?;
         let target = builder.target();
+        builder.use_build_queue(Arc::new(self.scheduler.clone()));
         self.targets.entry(target).or_insert_with(|| {
             self.target_count += 1;
-            let current_state = Arc::new(CurrentBuildState::new(builder.root_lib_name(), builder.builder_type()));
+            let current_state = Arc::new(CurrentBuildState::new(builder.root_lib_name(), builder.builder_type(), builder.runtime_environment()));
             let (outgoing, output) = builder.outgoing_channel();
 
             let handle = {
                 let mut outgoing = outgoing.resubscribe();
                 let mut output = output.resubscribe();
                 let current_state = current_state.clone();
+                let watcher = self.watcher.clone();
 
                 tokio::spawn(async move {
                     loop {
@@ -93,6 +117,14 @@ impl Manager {
                                 match msg {
                                     BuilderOutgoingMessages::Waiting => trace!("Builder for {target:?} is waiting"),
                                     BuilderOutgoingMessages::BuildStarted => trace!("Started building for {target:?}"),
+                                    BuilderOutgoingMessages::WatchCodeFolders(folders) => {
+                                        trace!("Watching {folders:?} for {target:?}");
+                                        if let Some(watcher) = &watcher {
+                                            if let Err(e) = watcher.watch_code_directories(&folders) {
+                                                error!("Couldn't watch code folders for {target:?} - {e}");
+                                            }
+                                        }
+                                    }
                                 }
                             }
                             Ok(msg) = output.recv() => {
@@ -140,6 +172,118 @@ impl Manager {
         Ok(response)
     }
 
+    pub fn runner_connected(&self, target: &Target, runner: uuid::Uuid) {
+        trace!("Runner {runner} connected to {target}");
+        let subscribers = {
+            let mut subscribers = self.subscribers.entry(*target).or_default();
+            *subscribers += 1;
+            *subscribers
+        };
+        if subscribers == 1 {
+            self.scheduler.set_watched(*target, true);
+            let _ = self
+                .watcher_channel
+                .send(BuilderIncomingMessages::ResumeBuilds(*target));
+        }
+    }
+
+    /// Runners keep earlier builds loaded, and later patches link against them, so every build they load stays referenced
+    pub fn runner_loaded_build(&self, target: &Target, runner: uuid::Uuid, build_id: u32) {
+        self.loaded_builds
+            .entry(*target)
+            .or_default()
+            .entry(runner)
+            .or_default()
+            .insert(build_id);
+        self.release_unreferenced_builds(target);
+    }
+
+    /// Counts the time until a runner loaded a build towards that build's broadcast timing
+    pub async fn build_reached_runner(&self, target: &Target, runner: uuid::Uuid, build_id: u32) {
+        let Some(current_state) = self.targets.get(target).map(|target| target.2.clone()) else {
+            return;
+        };
+        if let Some(elapsed) = current_state.build_loaded(build_id).await {
+            info!("Build {build_id} reached runner {runner} {elapsed:?} after completing");
+        }
+    }
+
+    /// A runner that loads a root library starts over, so only that build stays referenced for it
+    pub fn runner_loaded_root(&self, target: &Target, runner: uuid::Uuid, build_id: u32) {
+        self.loaded_builds
+            .entry(*target)
+            .or_default()
+            .insert(runner, HashSet::from([build_id]));
+        self.release_unreferenced_builds(target);
+    }
+
+    pub fn runner_disconnected(&self, target: &Target, runner: uuid::Uuid) {
+        if let Some(runners) = self.loaded_builds.get(target) {
+            runners.remove(&runner);
+        }
+        let subscribers = self
+            .subscribers
+            .get_mut(target)
+            .map(|mut subscribers| {
+                *subscribers = subscribers.saturating_sub(1);
+                *subscribers
+            })
+            .unwrap_or_default();
+        if subscribers == 0 {
+            info!("No runners connected to {target}, pausing its builds");
+            self.scheduler.set_watched(*target, false);
+            let _ = self
+                .watcher_channel
+                .send(BuilderIncomingMessages::PauseBuilds(*target));
+        }
+        self.release_unreferenced_builds(target);
+    }
+
+    fn release_unreferenced_builds(&self, target: &Target) {
+        let builds = self
+            .loaded_builds
+            .get(target)
+            .map(|runners| {
+                runners
+                    .iter()
+                    .flat_map(|runner| runner.value().clone())
+                    .collect::<HashSet<_>>()
+            })
+            .unwrap_or_default();
+        trace!("Builds referenced by runners for {target}: {builds:?}");
+        let _ = self
+            .watcher_channel
+            .send(BuilderIncomingMessages::ReferencedBuilds {
+                target: *target,
+                builds,
+            });
+
+        // Compressed copies are only served for published content, so the rest can go along with the old builds
+        let published = self.published_hashes();
+        tokio::task::spawn_blocking(move || {
+            match prune_compressed(Utf8Path::new(COMPRESSED_FILE_PATH), &published) {
+                Ok(0) => {}
+                Ok(removed) => trace!("Removed {removed} compressed copies"),
+                Err(e) => error!("Couldn't prune compressed copies - {e}"),
+            }
+        });
+    }
+
+    fn published_hashes(&self) -> HashSet<[u8; 32]> {
+        self.targets
+            .iter()
+            .flat_map(|target| {
+                let current_state = &target.value().2;
+                current_state
+                    .libraries
+                    .iter()
+                    .chain(current_state.assets.iter())
+                    .map(|record| record.hash)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
     pub fn get_filepath(
         &self,
         target: &Target,
@@ -163,12 +307,39 @@ impl Manager {
 
         Ok(file.local_path.clone())
     }
+
+    /// Finds a published library or asset by its content hash, from any target.
+    /// Files that changed on disk since they were published aren't returned, so the content always matches the hash.
+    pub fn get_blob(&self, hash: &[u8; 32]) -> Result<Utf8PathBuf, ManagerError> {
+        let hash_cache = HashCache::shared();
+        self.targets
+            .iter()
+            .flat_map(|target| {
+                let current_state = &target.value().2;
+                current_state
+                    .libraries
+                    .iter()
+                    .chain(current_state.assets.iter())
+                    .filter(|record| record.hash == *hash)
+                    .map(|record| record.local_path.clone())
+                    .collect::<Vec<_>>()
+            })
+            .find(|path| {
+                hash_cache
+                    .hash_file(path)
+                    .is_ok_and(|current| current == *hash)
+            })
+            .ok_or(ManagerError::NoSuchBlob)
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use dexterous_developer_builder::default_builder::retention::{
+        collect_unreferenced, BuildArtifact,
+    };
     use dexterous_developer_builder::types::{
         Builder, BuilderIncomingMessages, BuilderOutgoingMessages, HashedFileRecord, WatcherError,
     };
@@ -293,6 +464,48 @@ mod tests {
         assert!(matches!(err, ManagerError::MissingTarget(Target::IOS)));
     }
 
+    #[tokio::test]
+    async fn blobs_are_found_by_their_current_content() {
+        let manager = Manager::default()
+            .add_builder(TestBuilderInitializer)
+            .expect("Couldn't initialize builder");
+
+        let dir = Utf8PathBuf::try_from(std::env::temp_dir())
+            .unwrap()
+            .join(format!("blob-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("texture.png");
+        std::fs::write(&path, "texture").unwrap();
+        let hash = *blake3::hash(b"texture").as_bytes();
+
+        {
+            let target = manager.targets.get(&Target::Android).unwrap();
+            target
+                .2
+                .update(BuildOutputMessages::AssetUpdated(HashedFileRecord::new(
+                    "assets/texture.png",
+                    path.clone(),
+                    "texture.png",
+                    hash,
+                )))
+                .await;
+        }
+
+        assert_eq!(manager.get_blob(&hash).expect("Blob wasn't found"), path);
+        assert!(matches!(
+            manager.get_blob(&[1; 32]),
+            Err(ManagerError::NoSuchBlob)
+        ));
+
+        std::fs::write(&path, "changed texture").unwrap();
+        assert!(matches!(
+            manager.get_blob(&hash),
+            Err(ManagerError::NoSuchBlob)
+        ));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
     struct TestChanneledBuilderInitializer {
         target: Target,
     }
@@ -366,7 +579,7 @@ mod tests {
                                 break;
                             }
                         }
-                        if let BuilderIncomingMessages::CodeChanged = recv {
+                        if let BuilderIncomingMessages::CodeChanged(_) = recv {
                             output_tx
                                 .send(BuildOutputMessages::EndedBuild {
                                     libraries: vec![HashedFileRecord::new(
@@ -439,7 +652,9 @@ mod tests {
         }
 
         async fn update(&self) {
-            let _ = self.channel.send(BuilderIncomingMessages::CodeChanged);
+            let _ = self
+                .channel
+                .send(BuilderIncomingMessages::CodeChanged(vec![]));
         }
     }
 
@@ -553,4 +768,131 @@ mod tests {
             assert!(hash != new_hash, "Original: {hash:?}, new: {new_hash:?}");
         }
     }
+
+    #[tokio::test]
+    async fn builds_loaded_by_connected_runners_are_referenced() {
+        let manager = Manager::default()
+            .add_builder(TestBuilderInitializer)
+            .expect("Couldn't initialize builder");
+        let mut rx = manager.get_watcher_channel().subscribe();
+
+        let first = uuid::Uuid::new_v4();
+        let second = uuid::Uuid::new_v4();
+
+        manager.runner_loaded_build(&Target::Android, first, 1);
+        manager.runner_loaded_build(&Target::Android, second, 2);
+        manager.runner_disconnected(&Target::Android, first);
+
+        let mut last = None;
+        while let Ok(msg) = rx.try_recv() {
+            last = Some(msg);
+        }
+
+        let Some(BuilderIncomingMessages::ReferencedBuilds { target, builds }) = last else {
+            panic!("Didn't receive referenced builds");
+        };
+        assert_eq!(target, Target::Android);
+        assert_eq!(builds, [2].into_iter().collect());
+    }
+
+    fn last_referenced_builds(
+        rx: &mut broadcast::Receiver<BuilderIncomingMessages>,
+    ) -> HashSet<u32> {
+        let mut last = None;
+        while let Ok(msg) = rx.try_recv() {
+            last = Some(msg);
+        }
+        let Some(BuilderIncomingMessages::ReferencedBuilds { builds, .. }) = last else {
+            panic!("Didn't receive referenced builds");
+        };
+        builds
+    }
+
+    #[tokio::test]
+    async fn builds_a_runner_still_has_loaded_are_kept_outside_the_retention_window() {
+        let manager = Manager::default()
+            .add_builder(TestBuilderInitializer)
+            .expect("Couldn't initialize builder");
+        let mut rx = manager.get_watcher_channel().subscribe();
+
+        let runner = uuid::Uuid::new_v4();
+        manager.runner_loaded_root(&Target::Android, runner, 1);
+        for id in 2..=3 {
+            manager.runner_loaded_build(&Target::Android, runner, id);
+        }
+
+        let builds = last_referenced_builds(&mut rx);
+        assert_eq!(builds, [1, 2, 3].into_iter().collect());
+
+        let mut artifacts = (1..=3)
+            .map(|id| BuildArtifact {
+                id,
+                name: format!("test_lib.{id}"),
+                path: Utf8PathBuf::from(format!("libtest_lib.{id}.so")),
+            })
+            .collect::<Vec<_>>();
+        let removed = collect_unreferenced(&mut artifacts, &builds, 1);
+
+        assert!(removed.is_empty());
+        assert_eq!(
+            artifacts
+                .iter()
+                .map(|artifact| artifact.id)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[tokio::test]
+    async fn builds_are_released_once_a_runner_starts_over() {
+        let manager = Manager::default()
+            .add_builder(TestBuilderInitializer)
+            .expect("Couldn't initialize builder");
+        let mut rx = manager.get_watcher_channel().subscribe();
+
+        let runner = uuid::Uuid::new_v4();
+        for id in 1..=3 {
+            manager.runner_loaded_build(&Target::Android, runner, id);
+        }
+        manager.runner_loaded_root(&Target::Android, runner, 4);
+        assert_eq!(last_referenced_builds(&mut rx), [4].into_iter().collect());
+
+        manager.runner_disconnected(&Target::Android, runner);
+        assert!(last_referenced_builds(&mut rx).is_empty());
+    }
+
+    #[tokio::test]
+    async fn builds_pause_once_the_last_runner_disconnects() {
+        let manager = Manager::default()
+            .add_builder(TestBuilderInitializer)
+            .expect("Couldn't initialize builder");
+        let mut rx = manager.get_watcher_channel().subscribe();
+
+        let first = uuid::Uuid::new_v4();
+        let second = uuid::Uuid::new_v4();
+
+        manager.runner_connected(&Target::Android, first);
+        manager.runner_connected(&Target::Android, second);
+        manager.runner_disconnected(&Target::Android, first);
+        manager.runner_disconnected(&Target::Android, second);
+        manager.runner_connected(&Target::Android, first);
+
+        let mut messages = vec![];
+        while let Ok(msg) = rx.try_recv() {
+            match msg {
+                BuilderIncomingMessages::PauseBuilds(target) => messages.push((target, false)),
+                BuilderIncomingMessages::ResumeBuilds(target) => messages.push((target, true)),
+                _ => {}
+            }
+        }
+
+        assert_eq!(
+            messages,
+            vec![
+                (Target::Android, true),
+                (Target::Android, false),
+                (Target::Android, true)
+            ]
+        );
+    }
 }
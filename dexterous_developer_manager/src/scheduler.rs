@@ -0,0 +1,196 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use dexterous_developer_builder::types::{BuildPermit, BuildQueue, QueuedBuild};
+use dexterous_developer_types::Target;
+use tokio::sync::{oneshot, watch};
+use tracing::trace;
+
+pub const DEFAULT_CONCURRENT_BUILDS: usize = 1;
+
+/// Hands out build permits across targets, so only a limited number of cargo processes run at once.
+/// Targets with connected clients are served before the rest, otherwise builds run in the order they were queued.
+#[derive(Clone)]
+pub struct BuildScheduler {
+    state: Arc<Mutex<SchedulerState>>,
+}
+
+struct SchedulerState {
+    max_concurrent: usize,
+    running: usize,
+    next_sequence: u64,
+    queue: Vec<PendingBuild>,
+    watched: HashSet<Target>,
+}
+
+struct PendingBuild {
+    target: Target,
+    sequence: u64,
+    position: watch::Sender<usize>,
+    permit: oneshot::Sender<BuildPermit>,
+}
+
+impl Default for BuildScheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_CONCURRENT_BUILDS)
+    }
+}
+
+impl BuildScheduler {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SchedulerState {
+                max_concurrent: max_concurrent.max(1),
+                running: 0,
+                next_sequence: 0,
+                queue: vec![],
+                watched: HashSet::new(),
+            })),
+        }
+    }
+
+    pub fn set_max_concurrent(&self, max_concurrent: usize) {
+        if let Ok(mut state) = self.state.lock() {
+            state.max_concurrent = max_concurrent.max(1);
+        }
+        self.dispatch();
+    }
+
+    /// Marks whether a target has connected clients, which moves its builds ahead of unwatched targets.
+    pub fn set_watched(&self, target: Target, watched: bool) {
+        if let Ok(mut state) = self.state.lock() {
+            if watched {
+                state.watched.insert(target);
+            } else {
+                state.watched.remove(&target);
+            }
+        }
+        self.dispatch();
+    }
+
+    fn release(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.running = state.running.saturating_sub(1);
+        }
+        self.dispatch();
+    }
+
+    fn dispatch(&self) {
+        // Permits whose builder stopped waiting are dropped after the lock is released, since dropping one re-enters the scheduler
+        let mut abandoned = vec![];
+        {
+            let Ok(mut state) = self.state.lock() else {
+                return;
+            };
+            let SchedulerState { queue, watched, .. } = &mut *state;
+            queue.sort_by_key(|build| (!watched.contains(&build.target), build.sequence));
+
+            while state.running < state.max_concurrent && !state.queue.is_empty() {
+                let build = state.queue.remove(0);
+                trace!("Permitting build for {}", build.target);
+                state.running += 1;
+                let scheduler = self.clone();
+                if let Err(permit) = build
+                    .permit
+                    .send(BuildPermit::new(move || scheduler.release()))
+                {
+                    abandoned.push(permit);
+                }
+            }
+
+            for (index, build) in state.queue.iter().enumerate() {
+                build.position.send_replace(index + 1);
+            }
+        }
+        drop(abandoned);
+    }
+}
+
+impl BuildQueue for BuildScheduler {
+    fn enqueue(&self, target: Target) -> QueuedBuild {
+        let (position_tx, position) = watch::channel(0);
+        let (permit_tx, permit) = oneshot::channel();
+        if let Ok(mut state) = self.state.lock() {
+            let sequence = state.next_sequence;
+            state.next_sequence += 1;
+            state.queue.push(PendingBuild {
+                target,
+                sequence,
+                position: position_tx,
+                permit: permit_tx,
+            });
+        }
+        self.dispatch();
+        QueuedBuild { position, permit }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn limits_concurrent_builds() {
+        let scheduler = BuildScheduler::new(1);
+
+        let first = scheduler.enqueue(Target::Linux);
+        let mut second = scheduler.enqueue(Target::Windows);
+
+        let first_permit = first.permit.await.expect("First build wasn't permitted");
+        assert_eq!(*second.position.borrow(), 1);
+        assert!(second.permit.try_recv().is_err());
+
+        drop(first_permit);
+        second
+            .permit
+            .await
+            .expect("Second build wasn't permitted after the first finished");
+    }
+
+    #[tokio::test]
+    async fn prioritises_targets_with_connected_clients() {
+        let scheduler = BuildScheduler::new(1);
+
+        let running = scheduler
+            .enqueue(Target::Linux)
+            .permit
+            .await
+            .expect("Initial build wasn't permitted");
+
+        let unwatched = scheduler.enqueue(Target::Windows);
+        let watched = scheduler.enqueue(Target::Android);
+        assert_eq!(*unwatched.position.borrow(), 1);
+        assert_eq!(*watched.position.borrow(), 2);
+
+        scheduler.set_watched(Target::Android, true);
+        assert_eq!(*watched.position.borrow(), 1);
+        assert_eq!(*unwatched.position.borrow(), 2);
+
+        drop(running);
+        watched
+            .permit
+            .await
+            .expect("Watched target wasn't permitted first");
+    }
+
+    #[tokio::test]
+    async fn abandoned_builds_release_their_slot() {
+        let scheduler = BuildScheduler::new(1);
+
+        let running = scheduler
+            .enqueue(Target::Linux)
+            .permit
+            .await
+            .expect("Initial build wasn't permitted");
+        drop(scheduler.enqueue(Target::Windows));
+        let waiting = scheduler.enqueue(Target::Android);
+
+        drop(running);
+        waiting
+            .permit
+            .await
+            .expect("Build wasn't permitted after an abandoned one");
+    }
+}
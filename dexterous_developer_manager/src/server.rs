@@ -11,16 +11,22 @@ use axum::{
         ws::{self, WebSocket},
         Path, Request, State, WebSocketUpgrade,
     },
-    http::StatusCode,
+    http::{
+        header::{CONTENT_ENCODING, CONTENT_TYPE, VARY},
+        HeaderValue, StatusCode,
+    },
     response::{IntoResponse, Response},
     routing::get,
     Router,
 };
-use camino::Utf8PathBuf;
-use dexterous_developer_builder::types::{
-    BuildOutputMessages, CurrentBuildState, HashedFileRecord,
+use camino::{Utf8Path, Utf8PathBuf};
+use dexterous_developer_builder::{
+    hash_cache::HashCache,
+    types::{BuildOutputMessages, CurrentBuildState, HashedFileRecord},
+};
+use dexterous_developer_types::{
+    HotReloadMessage, HotReloadRunnerMessage, Target, TargetParseError,
 };
-use dexterous_developer_types::{HotReloadMessage, Target, TargetParseError};
 use futures_util::{SinkExt, StreamExt};
 use thiserror::Error;
 use tokio::sync::broadcast;
@@ -28,13 +34,17 @@ use tower::ServiceExt;
 use tower_http::services::ServeFile;
 use tracing::{error, info, trace};
 
-use crate::{Manager, ManagerError};
+use crate::{
+    compression::{accepts_zstd, compressed_copy, COMPRESSED_FILE_PATH},
+    Manager, ManagerError,
+};
 
 pub async fn run_server(port: u16, manager: Manager) -> Result<(), Error> {
     let app = Router::new()
         .route("/targets", get(list_targets))
         .route("/target/:target", get(connect_to_target))
-        .route("/files/:target/*file", get(target_file_loader));
+        .route("/files/:target/*file", get(target_file_loader))
+        .route("/blob/:hash", get(blob_loader));
 
     let app = app.with_state(ServerState {
         manager: Arc::new(manager),
@@ -60,7 +70,8 @@ pub async fn run_test_server(
     let app = Router::new()
         .route("/targets", get(list_targets))
         .route("/target/:target", get(connect_to_target))
-        .route("/files/:target/*file", get(target_file_loader));
+        .route("/files/:target/*file", get(target_file_loader))
+        .route("/blob/:hash", get(blob_loader));
 
     let app = app.with_state(ServerState {
         manager: Arc::new(manager),
@@ -127,20 +138,52 @@ async fn connect_to_target(
             error!("Connection Error - {id} {target:?}: {e}");
             e
         })?;
-    Ok(ws.on_upgrade(move |socket| {
-        connected_to_target(id, socket, target, initial_build_state, builder_rx)
+    let manager = state.manager.clone();
+    Ok(ws.on_upgrade(move |socket| async move {
+        manager.runner_connected(&target, id);
+        connected_to_target(
+            id,
+            socket,
+            target,
+            initial_build_state,
+            builder_rx,
+            manager.clone(),
+        )
+        .await;
+        manager.runner_disconnected(&target, id);
     }))
 }
 
 async fn connected_to_target(
     id: uuid::Uuid,
     socket: WebSocket,
-    _target: Target,
+    target: Target,
     initial_build_state: CurrentBuildState,
     mut builder_rx: broadcast::Receiver<BuildOutputMessages>,
+    manager: Arc<Manager>,
 ) {
     info!("Client {id} Connected");
-    let (mut ws_sender, _) = socket.split();
+    let (mut ws_sender, mut ws_receiver) = socket.split();
+
+    tokio::spawn(async move {
+        while let Some(Ok(msg)) = ws_receiver.next().await {
+            let ws::Message::Binary(msg) = msg else {
+                continue;
+            };
+            match rmp_serde::from_slice::<HotReloadRunnerMessage>(&msg) {
+                Ok(HotReloadRunnerMessage::LoadedBuild(build_id)) => {
+                    trace!("Client {id} loaded build {build_id}");
+                    manager.runner_loaded_build(&target, id, build_id);
+                    manager.build_reached_runner(&target, id, build_id).await;
+                }
+                Ok(HotReloadRunnerMessage::LoadedRoot(build_id)) => {
+                    trace!("Client {id} loaded root build {build_id}");
+                    manager.runner_loaded_root(&target, id, build_id);
+                }
+                Err(e) => error!("Couldn't parse message from {id} - {e}"),
+            }
+        }
+    });
 
     {
         let initial_state_message = HotReloadMessage::InitialState {
@@ -168,6 +211,7 @@ async fn connected_to_target(
                 .most_recent_completed_build
                 .load(std::sync::atomic::Ordering::SeqCst),
             builder_type: initial_build_state.builder_type,
+            environment: initial_build_state.runtime_environment.clone(),
         };
         let Ok(message) = rmp_serde::to_vec(&initial_state_message) else {
             error!("Failed to serialize initial state message for {id}");
@@ -180,12 +224,42 @@ async fn connected_to_target(
             let _ = ws_sender.close().await;
             return;
         }
+
+        let diagnostics = {
+            let lock = initial_build_state.diagnostics.lock().await;
+            lock.clone()
+        };
+        if !diagnostics.is_empty() {
+            let diagnostics_message = HotReloadMessage::BuildDiagnostics {
+                id: initial_build_state
+                    .most_recent_started_build
+                    .load(std::sync::atomic::Ordering::SeqCst),
+                diagnostics,
+            };
+            let Ok(message) = rmp_serde::to_vec(&diagnostics_message) else {
+                error!("Failed to serialize initial diagnostics for {id}");
+                let _ = ws_sender.close().await;
+                return;
+            };
+
+            if let Err(e) = ws_sender.send(ws::Message::Binary(message)).await {
+                error!("Failed to send initial diagnostics to {id} - {e}");
+                let _ = ws_sender.close().await;
+                return;
+            }
+        }
     }
 
     while let Ok(msg) = tokio::select! {
         val = builder_rx.recv() => {
             val.map(|msg| match &msg {
                 BuildOutputMessages::AssetUpdated(HashedFileRecord {  relative_path, hash, .. }) => Some(HotReloadMessage::UpdatedAssets(relative_path.clone(), *hash)),
+                BuildOutputMessages::AssetRemoved(relative_path) => Some(HotReloadMessage::RemovedAsset(relative_path.clone())),
+                BuildOutputMessages::AssetRenamed { from, to } => Some(HotReloadMessage::RenamedAsset {
+                    from: from.clone(),
+                    to: to.relative_path.clone(),
+                    hash: to.hash
+                }),
                 BuildOutputMessages::KeepAlive => None,
                 BuildOutputMessages::StartedBuild(id) => Some(HotReloadMessage::BuildStarted(*id)),
                 BuildOutputMessages::EndedBuild { id, libraries, root_library } => Some(HotReloadMessage::BuildCompleted {
@@ -193,10 +267,23 @@ async fn connected_to_target(
                     libraries: libraries.iter().map(|library| (library.name.clone(), library.hash, library.dependencies.clone())).collect(),
                     root_library: root_library.clone()
                 }),
+                BuildOutputMessages::CompilerDiagnostic { id, diagnostic } => Some(HotReloadMessage::BuildDiagnostics {
+                    id: *id,
+                    diagnostics: vec![diagnostic.clone()]
+                }),
+                BuildOutputMessages::BuildTimings { id, timings } => Some(HotReloadMessage::BuildTimings {
+                    id: *id,
+                    timings: *timings
+                }),
+                BuildOutputMessages::BuildQueued { id, position } => Some(HotReloadMessage::BuildQueued {
+                    id: *id,
+                    position: *position
+                }),
                 BuildOutputMessages::FailedBuild(e) => {
                     error!("Failed Build - {e}");
                     None
                 }
+                BuildOutputMessages::CancelledBuild(id) => Some(HotReloadMessage::BuildCancelled(*id)),
             })
         }
         _ = tokio::time::sleep(Duration::from_secs(5)) => Ok(Some(HotReloadMessage::KeepAlive))
@@ -236,8 +323,180 @@ async fn target_file_loader(
         }
     };
     trace!("Found File path: {file:?}");
-    let serve = ServeFile::new(file);
-    let result = serve.oneshot(request).await?;
+    let result = serve_file(file, None, Utf8Path::new(COMPRESSED_FILE_PATH), request).await?;
     trace!("Result has status {:?}", result.status());
-    Ok(result.into_response())
+    Ok(result)
+}
+
+async fn blob_loader(
+    Path(hash): Path<String>,
+    state: State<ServerState>,
+    request: Request<Body>,
+) -> Result<Response, Error> {
+    let Ok(hash) = blake3::Hash::from_hex(&hash) else {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    };
+    trace!("Requested blob {hash}");
+    let file = match state.manager.get_blob(hash.as_bytes()) {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Couldn't Find Blob For Download {e:?}");
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        }
+    };
+    trace!("Found Blob path: {file:?}");
+    serve_file(
+        file,
+        Some(*hash.as_bytes()),
+        Utf8Path::new(COMPRESSED_FILE_PATH),
+        request,
+    )
+    .await
+}
+
+/// Serves a file with range support, compressed with zstd if the client accepts it.
+///
+/// If the content no longer matches the expected hash, or compression fails, the file is served as is.
+async fn serve_file(
+    file: Utf8PathBuf,
+    expected_hash: Option<[u8; 32]>,
+    cache_dir: &Utf8Path,
+    request: Request<Body>,
+) -> Result<Response, Error> {
+    let compressed = if accepts_zstd(request.headers()) {
+        let source = file.clone();
+        let cache_dir = cache_dir.to_owned();
+        match tokio::task::spawn_blocking(move || {
+            compressed_copy(&source, &cache_dir, &HashCache::shared())
+        })
+        .await
+        {
+            Ok(Ok((compressed, hash))) if expected_hash.is_none_or(|expected| expected == hash) => {
+                Some(compressed)
+            }
+            Ok(Ok(_)) => {
+                trace!("{file} changed since it was requested, sending it uncompressed");
+                None
+            }
+            Ok(Err(e)) => {
+                error!("Couldn't compress {file} - {e}");
+                None
+            }
+            Err(e) => {
+                error!("Compression task for {file} failed - {e}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let Some(compressed) = compressed else {
+        let result = ServeFile::new(file).oneshot(request).await?;
+        return Ok(result.into_response());
+    };
+
+    let mut result = ServeFile::new(compressed)
+        .oneshot(request)
+        .await?
+        .into_response();
+    result
+        .headers_mut()
+        .insert(VARY, HeaderValue::from_static("accept-encoding"));
+    if result.status().is_success() {
+        let headers = result.headers_mut();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("zstd"));
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/octet-stream"),
+        );
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::header::{ACCEPT_ENCODING, CONTENT_RANGE, RANGE};
+
+    use super::*;
+
+    fn test_dir() -> Utf8PathBuf {
+        let dir = Utf8PathBuf::try_from(std::env::temp_dir())
+            .unwrap()
+            .join(format!("serve-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn ranged_request(range: &str) -> Request<Body> {
+        Request::builder()
+            .header(ACCEPT_ENCODING, "zstd")
+            .header(RANGE, range)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn resumes_the_compressed_body_from_an_offset() {
+        let dir = test_dir();
+        let file = dir.join("library.so");
+        let content = (0..20_000u32)
+            .flat_map(|i| i.to_le_bytes())
+            .collect::<Vec<_>>();
+        std::fs::write(&file, &content).unwrap();
+        let hash = *blake3::hash(&content).as_bytes();
+        let cache = dir.join("cache");
+
+        let full = serve_file(file.clone(), Some(hash), &cache, ranged_request("bytes=0-"))
+            .await
+            .expect("Couldn't serve file");
+        let full = axum::body::to_bytes(full.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(zstd::stream::decode_all(full.as_ref()).unwrap(), content);
+
+        let offset = full.len() / 2;
+        let response = serve_file(
+            file,
+            Some(hash),
+            &cache,
+            ranged_request(&format!("bytes={offset}-")),
+        )
+        .await
+        .expect("Couldn't serve file");
+
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers().get(CONTENT_ENCODING).unwrap(), "zstd");
+        assert_eq!(
+            response.headers().get(CONTENT_RANGE).unwrap(),
+            &format!("bytes {offset}-{}/{}", full.len() - 1, full.len())
+        );
+        let rest = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(rest, full.slice(offset..));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_range_past_the_compressed_body() {
+        let dir = test_dir();
+        let file = dir.join("library.so");
+        std::fs::write(&file, "library content").unwrap();
+
+        let response = serve_file(
+            file,
+            None,
+            &dir.join("cache"),
+            ranged_request("bytes=100000-"),
+        )
+        .await
+        .expect("Couldn't serve file");
+
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -1,12 +1,17 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::trace;
 
-use crate::{BuilderTypes, PackageOrExample, Target, TargetBuildSettings};
+use crate::{
+    AssetProcessor, BuilderTypes, InFlightBuildPolicy, Linker, OptLevel, PackageOrExample, Target,
+    TargetBuildSettings, WatcherBackend,
+};
 use camino::Utf8PathBuf;
 
+pub const DEFAULT_POLL_INTERVAL_MS: u64 = 1000;
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct DexterousConfig {
     #[serde(default)]
@@ -16,23 +21,57 @@ pub struct DexterousConfig {
     #[serde(default)]
     pub code_watch_folders: Vec<camino::Utf8PathBuf>,
     #[serde(default)]
+    pub code_watch_include: Vec<String>,
+    #[serde(default)]
+    pub code_watch_exclude: Vec<String>,
+    #[serde(default)]
+    pub respect_gitignore: Option<bool>,
+    #[serde(default)]
+    pub watcher: Option<WatcherBackend>,
+    #[serde(default)]
+    pub poll_interval_ms: Option<u64>,
+    #[serde(default)]
     pub port: Option<u16>,
     #[serde(default)]
+    pub max_concurrent_builds: Option<usize>,
+    #[serde(default)]
     pub targets: HashMap<Target, ReloadTargetConfig>,
     #[serde(default)]
     pub packages: HashMap<String, ReloadTargetConfig>,
     #[serde(default)]
     pub examples: HashMap<String, ReloadTargetConfig>,
     #[serde(default)]
+    pub bins: HashMap<String, ReloadTargetConfig>,
+    #[serde(default)]
     pub default_package: Option<ReloadTargetConfig>,
     #[serde(default)]
     pub environment: HashMap<String, String>,
     #[serde(default)]
+    pub runtime_environment: HashMap<String, String>,
+    #[serde(default)]
     pub manifest_path: Option<Utf8PathBuf>,
     #[serde(default)]
     pub additional_library_directories: Vec<Utf8PathBuf>,
     #[serde(default)]
     pub apple_sdk_directory: Vec<Utf8PathBuf>,
+    #[serde(default)]
+    pub in_flight_build: Option<InFlightBuildPolicy>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub opt_level: Option<OptLevel>,
+    #[serde(default)]
+    pub retained_builds: Option<usize>,
+    #[serde(default)]
+    pub linker: Option<Linker>,
+    #[serde(default)]
+    pub toolchain: Option<String>,
+    #[serde(default)]
+    pub reloadable_crates: Vec<String>,
+    #[serde(default)]
+    pub check_before_build: Option<bool>,
+    #[serde(default)]
+    pub asset_processors: Vec<AssetProcessor>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -44,6 +83,8 @@ pub struct ReloadTargetConfig {
     #[serde(default)]
     pub environment: HashMap<String, String>,
     #[serde(default)]
+    pub runtime_environment: HashMap<String, String>,
+    #[serde(default)]
     pub builder: Option<BuilderTypes>,
     #[serde(default)]
     pub manifest_path: Option<Utf8PathBuf>,
@@ -51,6 +92,24 @@ pub struct ReloadTargetConfig {
     pub additional_library_directories: Vec<Utf8PathBuf>,
     #[serde(default)]
     pub apple_sdk_directory: Vec<Utf8PathBuf>,
+    #[serde(default)]
+    pub in_flight_build: Option<InFlightBuildPolicy>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub opt_level: Option<OptLevel>,
+    #[serde(default)]
+    pub retained_builds: Option<usize>,
+    #[serde(default)]
+    pub linker: Option<Linker>,
+    #[serde(default)]
+    pub toolchain: Option<String>,
+    #[serde(default)]
+    pub reloadable_crates: Vec<String>,
+    #[serde(default)]
+    pub check_before_build: Option<bool>,
+    #[serde(default)]
+    pub asset_processors: Vec<AssetProcessor>,
 }
 
 impl DexterousConfig {
@@ -78,6 +137,11 @@ impl DexterousConfig {
         let config = toml::from_str(toml)?;
         Ok(config)
     }
+
+    /// How often the polling watcher scans for changes - only used with `watcher = "poll"`
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms.unwrap_or(DEFAULT_POLL_INTERVAL_MS))
+    }
 }
 
 #[derive(Error, Debug)]
@@ -106,10 +170,43 @@ impl DexterousConfig {
             PackageOrExample::Example(example) => {
                 self.examples.get(example).cloned().unwrap_or_default()
             }
+            PackageOrExample::Bin(bin) => self.bins.get(bin).cloned().unwrap_or_default(),
         };
 
         let global_builder = package_specific_config.builder;
 
+        let global_in_flight_build = package_specific_config
+            .in_flight_build
+            .or(self.in_flight_build);
+
+        let global_profile = package_specific_config
+            .profile
+            .as_ref()
+            .or(self.profile.as_ref());
+
+        let global_opt_level = package_specific_config
+            .opt_level
+            .as_ref()
+            .or(self.opt_level.as_ref());
+
+        let global_retained_builds = package_specific_config
+            .retained_builds
+            .or(self.retained_builds);
+
+        let global_linker = package_specific_config
+            .linker
+            .as_ref()
+            .or(self.linker.as_ref());
+
+        let global_toolchain = package_specific_config
+            .toolchain
+            .as_ref()
+            .or(self.toolchain.as_ref());
+
+        let global_check_before_build = package_specific_config
+            .check_before_build
+            .or(self.check_before_build);
+
         let global_manifest = package_specific_config
             .manifest_path
             .as_ref()
@@ -133,6 +230,12 @@ impl DexterousConfig {
             .chain(self.environment.iter())
             .map(|(key, value)| (key.to_owned(), value.to_owned()))
             .collect::<HashMap<_, _>>();
+        let global_runtime_environment_variables = package_specific_config
+            .runtime_environment
+            .iter()
+            .chain(self.runtime_environment.iter())
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect::<HashMap<_, _>>();
         let global_library_directories = package_specific_config
             .additional_library_directories
             .iter()
@@ -146,36 +249,30 @@ impl DexterousConfig {
             .cloned()
             .collect::<Vec<_>>();
 
+        let global_reloadable_crates = package_specific_config
+            .reloadable_crates
+            .iter()
+            .chain(self.reloadable_crates.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let global_asset_processors = package_specific_config
+            .asset_processors
+            .iter()
+            .chain(self.asset_processors.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+
         let mut targets = self
             .targets
             .iter()
-            .map(|(target, settings)| {
-                (
-                    *target,
-                    settings.features.clone(),
-                    settings.asset_folders.clone(),
-                    settings.environment.clone(),
-                    settings.builder,
-                    settings.manifest_path.clone(),
-                    settings.additional_library_directories.clone(),
-                    settings.apple_sdk_directory.clone(),
-                )
-            })
+            .map(|(target, settings)| (*target, settings.clone()))
             .collect::<Vec<_>>();
 
         if targets.is_empty() {
             let default_target =
                 Target::current().ok_or(BuildSettingsGenerationError::NoDefaultTarget)?;
-            targets.push((
-                default_target,
-                vec![],
-                vec![],
-                HashMap::new(),
-                None,
-                None,
-                vec![],
-                vec![],
-            ))
+            targets.push((default_target, ReloadTargetConfig::default()))
         }
 
         Ok(targets
@@ -183,13 +280,25 @@ impl DexterousConfig {
             .map(
                 move |(
                     target,
-                    mut features,
-                    mut asset_folders,
-                    mut environment,
-                    builder,
-                    manifest_path,
-                    mut additional_library_directories,
-                    mut apple_sdk_directory,
+                    ReloadTargetConfig {
+                        mut features,
+                        mut asset_folders,
+                        mut environment,
+                        mut runtime_environment,
+                        builder,
+                        manifest_path,
+                        mut additional_library_directories,
+                        mut apple_sdk_directory,
+                        in_flight_build,
+                        profile,
+                        opt_level,
+                        retained_builds,
+                        linker,
+                        toolchain,
+                        mut reloadable_crates,
+                        check_before_build,
+                        mut asset_processors,
+                    },
                 )| {
                     for f in global_features.iter() {
                         features.push(f.to_string());
@@ -203,9 +312,18 @@ impl DexterousConfig {
                     for (key, value) in global_environment_variables.iter() {
                         environment.insert(key.to_owned(), value.to_owned());
                     }
+                    for (key, value) in global_runtime_environment_variables.iter() {
+                        runtime_environment.insert(key.to_owned(), value.to_owned());
+                    }
                     for l in global_apple_sdk.iter() {
                         apple_sdk_directory.push(l.clone());
                     }
+                    for c in global_reloadable_crates.iter() {
+                        if !reloadable_crates.contains(c) {
+                            reloadable_crates.push(c.clone());
+                        }
+                    }
+                    asset_processors.extend(global_asset_processors.iter().cloned());
                     (
                         target,
                         TargetBuildSettings {
@@ -215,6 +333,7 @@ impl DexterousConfig {
                             asset_folders,
                             code_watch_folders: self.code_watch_folders.clone(),
                             environment,
+                            runtime_environment,
                             builder: global_builder
                                 .as_ref()
                                 .cloned()
@@ -223,6 +342,19 @@ impl DexterousConfig {
                             manifest_path: manifest_path.or(global_manifest.cloned()),
                             additional_library_directories,
                             apple_sdk_directory,
+                            in_flight_build: in_flight_build
+                                .or(global_in_flight_build)
+                                .unwrap_or_default(),
+                            profile: profile.or(global_profile.cloned()),
+                            opt_level: opt_level.or(global_opt_level.cloned()),
+                            retained_builds: retained_builds.or(global_retained_builds),
+                            linker: linker.or(global_linker.cloned()).unwrap_or_default(),
+                            toolchain: toolchain.or(global_toolchain.cloned()),
+                            reloadable_crates,
+                            check_before_build: check_before_build
+                                .or(global_check_before_build)
+                                .unwrap_or_default(),
+                            asset_processors,
                         },
                     )
                 },
@@ -239,10 +371,12 @@ pub enum BuildSettingsGenerationError {
 
 #[cfg(test)]
 mod test {
-    use crate::{PackageOrExample, Target};
+    use std::time::Duration;
+
+    use crate::{InFlightBuildPolicy, Linker, OptLevel, PackageOrExample, Target, WatcherBackend};
     use camino::Utf8PathBuf;
 
-    use super::{DexterousConfig, ReloadTargetConfig};
+    use super::{DexterousConfig, ReloadTargetConfig, DEFAULT_POLL_INTERVAL_MS};
 
     #[test]
     fn given_a_manifest_with_no_metadata_provides_default_target() {
@@ -281,10 +415,20 @@ mod test {
                     environment: [("env".to_string(), "value".to_string())]
                         .into_iter()
                         .collect(),
+                    runtime_environment: Default::default(),
                     builder: None,
                     manifest_path: None,
                     additional_library_directories: vec![],
                     apple_sdk_directory: vec![],
+                    in_flight_build: None,
+                    profile: None,
+                    opt_level: None,
+                    retained_builds: None,
+                    linker: None,
+                    toolchain: None,
+                    reloadable_crates: vec![],
+                    check_before_build: None,
+                    asset_processors: vec![],
                 },
             )])
             .into_iter()
@@ -494,4 +638,308 @@ mod test {
             "/asset"
         );
     }
+
+    #[test]
+    fn given_an_in_flight_build_policy_target_settings_override_global_ones() {
+        let toml = r#"
+        in_flight_build = "Restart"
+
+        [targets.x86_64-pc-windows-msvc]
+        in_flight_build = "Complete"
+
+        [targets.x86_64-unknown-linux-gnu]
+        "#;
+
+        let config = DexterousConfig::load_toml_from_str(toml).expect("Couldn't load toml");
+        let build_settings = config
+            .generate_build_settings(None, &[])
+            .expect("Couldn't generate build settings");
+
+        assert_eq!(build_settings.len(), 2);
+
+        for (target, settings) in build_settings {
+            match target {
+                Target::Windows => {
+                    assert_eq!(settings.in_flight_build, InFlightBuildPolicy::Complete)
+                }
+                Target::Linux => assert_eq!(settings.in_flight_build, InFlightBuildPolicy::Restart),
+                target => panic!("Unexpected target {target}"),
+            }
+        }
+    }
+
+    #[test]
+    fn given_a_profile_and_opt_level_provides_them() {
+        let toml = r#"
+        profile = "hot"
+
+        [targets.x86_64-unknown-linux-gnu]
+        opt_level = 1
+
+        [targets.x86_64-pc-windows-msvc]
+        profile = "dev"
+        opt_level = "s"
+        "#;
+
+        let config = DexterousConfig::load_toml_from_str(toml).expect("Couldn't load toml");
+        let build_settings = config
+            .generate_build_settings(None, &[])
+            .expect("Couldn't generate build settings");
+
+        for (target, settings) in build_settings {
+            match target {
+                Target::Linux => {
+                    assert_eq!(settings.profile(), "hot");
+                    assert_eq!(settings.profile_directory(), "hot");
+                    assert_eq!(settings.opt_level, Some(OptLevel::Level(1)));
+                }
+                Target::Windows => {
+                    assert_eq!(settings.profile(), "dev");
+                    assert_eq!(settings.profile_directory(), "debug");
+                    assert_eq!(settings.opt_level, Some(OptLevel::Named("s".to_string())));
+                }
+                target => panic!("Unexpected target {target}"),
+            }
+        }
+    }
+
+    #[test]
+    fn given_a_linker_provides_it() {
+        let toml = r#"
+        linker = "mold"
+
+        [targets.x86_64-unknown-linux-gnu]
+
+        [targets.x86_64-pc-windows-msvc]
+        linker = "custom:/opt/bin/my-linker"
+        "#;
+
+        let config = DexterousConfig::load_toml_from_str(toml).expect("Couldn't load toml");
+        let build_settings = config
+            .generate_build_settings(None, &[])
+            .expect("Couldn't generate build settings");
+
+        for (target, settings) in build_settings {
+            match target {
+                Target::Linux => {
+                    assert_eq!(settings.linker, Linker::Mold);
+                    assert_eq!(
+                        settings.linker.rustflags(target),
+                        vec!["-Clink-arg=-fuse-ld=mold".to_string()]
+                    );
+                }
+                Target::Windows => {
+                    assert_eq!(
+                        settings.linker,
+                        Linker::Custom(Utf8PathBuf::from("/opt/bin/my-linker"))
+                    );
+                    assert_eq!(
+                        settings.linker.rustflags(target),
+                        vec!["-Clinker=/opt/bin/my-linker".to_string()]
+                    );
+                }
+                target => panic!("Unexpected target {target}"),
+            }
+        }
+    }
+
+    #[test]
+    fn given_an_unknown_linker_provides_an_error() {
+        let toml = r#"
+        linker = "zg"
+        "#;
+
+        assert!(DexterousConfig::load_toml_from_str(toml).is_err());
+
+        let toml = r#"
+        linker = "custom:"
+        "#;
+
+        assert!(DexterousConfig::load_toml_from_str(toml).is_err());
+    }
+
+    #[test]
+    fn given_build_and_runtime_environments_provides_them_with_global_precedence() {
+        let toml = r#"
+        [environment]
+        SHARED = "global"
+        GLOBAL_ONLY = "global"
+
+        [runtime_environment]
+        RUST_LOG = "info"
+
+        [targets.x86_64-unknown-linux-gnu.environment]
+        SHARED = "target"
+
+        [targets.x86_64-unknown-linux-gnu.runtime_environment]
+        RUST_LOG = "debug"
+        "#;
+
+        let config = DexterousConfig::load_toml_from_str(toml).expect("Couldn't load toml");
+        let build_settings = config
+            .generate_build_settings(None, &[])
+            .expect("Couldn't generate build settings");
+
+        let (_, settings) = build_settings.first().expect("No Targets Set Up");
+
+        assert_eq!(settings.environment.get("SHARED").unwrap(), "global");
+        assert_eq!(settings.environment.get("GLOBAL_ONLY").unwrap(), "global");
+        assert_eq!(
+            settings.runtime_environment.get("RUST_LOG").unwrap(),
+            "info"
+        );
+        assert!(!settings.environment.contains_key("RUST_LOG"));
+    }
+
+    #[test]
+    fn given_a_toolchain_provides_it_per_target() {
+        let toml = r#"
+        toolchain = "stable"
+
+        [targets.x86_64-unknown-linux-gnu]
+        toolchain = "nightly-2024-08-01"
+
+        [targets.x86_64-pc-windows-msvc]
+        "#;
+
+        let config = DexterousConfig::load_toml_from_str(toml).expect("Couldn't load toml");
+        let build_settings = config
+            .generate_build_settings(None, &[])
+            .expect("Couldn't generate build settings");
+
+        for (target, settings) in build_settings {
+            match target {
+                Target::Linux => {
+                    assert_eq!(settings.toolchain.as_deref(), Some("nightly-2024-08-01"))
+                }
+                Target::Windows => assert_eq!(settings.toolchain.as_deref(), Some("stable")),
+                target => panic!("Unexpected target {target}"),
+            }
+        }
+    }
+
+    #[test]
+    fn given_reloadable_crates_provides_them_without_duplicates() {
+        let toml = r#"
+        reloadable_crates = ["gameplay", "ui"]
+
+        [targets.x86_64-unknown-linux-gnu]
+        reloadable_crates = ["ui", "levels"]
+        "#;
+
+        let config = DexterousConfig::load_toml_from_str(toml).expect("Couldn't load toml");
+        let build_settings = config
+            .generate_build_settings(None, &[])
+            .expect("Couldn't generate build settings");
+
+        let (_, settings) = build_settings.first().expect("No Targets Set Up");
+
+        assert_eq!(settings.reloadable_crates, vec!["ui", "levels", "gameplay"]);
+    }
+
+    #[test]
+    fn given_check_before_build_provides_it_per_target() {
+        let toml = r#"
+        check_before_build = true
+
+        [targets.x86_64-unknown-linux-gnu]
+        check_before_build = false
+
+        [targets.x86_64-pc-windows-msvc]
+        "#;
+
+        let config = DexterousConfig::load_toml_from_str(toml).expect("Couldn't load toml");
+        let build_settings = config
+            .generate_build_settings(None, &[])
+            .expect("Couldn't generate build settings");
+
+        for (target, settings) in build_settings {
+            match target {
+                Target::Linux => assert!(!settings.check_before_build),
+                Target::Windows => assert!(settings.check_before_build),
+                target => panic!("Unexpected target {target}"),
+            }
+        }
+    }
+
+    #[test]
+    fn given_a_manifest_with_a_bin_provides_the_bin_config() {
+        let toml = r#"
+        features = ["global"]
+
+        [bins.editor]
+        features = ["editor-tools"]
+
+        [bins.client]
+        features = ["shouldnt-load"]
+        "#;
+
+        let config = DexterousConfig::load_toml_from_str(toml).expect("Couldn't load toml");
+        let build_settings = config
+            .generate_build_settings(Some(PackageOrExample::Bin("editor".to_string())), &[])
+            .expect("Couldn't generate build settings");
+
+        let (_, settings) = build_settings.first().expect("No Targets Set Up");
+
+        assert_eq!(
+            settings.package_or_example,
+            PackageOrExample::Bin("editor".to_string())
+        );
+        assert_eq!(settings.features, vec!["editor-tools", "global"]);
+    }
+
+    #[test]
+    fn given_a_poll_watcher_provides_it_with_an_interval() {
+        let default = DexterousConfig::load_toml_from_str("").expect("Couldn't load toml");
+        assert_eq!(default.watcher.unwrap_or_default(), WatcherBackend::Native);
+        assert_eq!(
+            default.poll_interval(),
+            Duration::from_millis(DEFAULT_POLL_INTERVAL_MS)
+        );
+
+        let toml = r#"
+        watcher = "poll"
+        poll_interval_ms = 250
+        "#;
+
+        let config = DexterousConfig::load_toml_from_str(toml).expect("Couldn't load toml");
+
+        assert_eq!(config.watcher, Some(WatcherBackend::Poll));
+        assert_eq!(config.poll_interval(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn given_asset_processors_provides_them_with_target_ones_first() {
+        let toml = r#"
+        [[asset_processors]]
+        globs = ["**/*.wgsl"]
+        command = ["naga", "{input}", "{input_dir}/{stem}.spv"]
+        outputs = ["{input_dir}/{stem}.spv"]
+
+        [targets.x86_64-unknown-linux-gnu]
+
+        [[targets.x86_64-unknown-linux-gnu.asset_processors]]
+        globs = ["**/*.ase"]
+        command = ["aseprite", "-b", "{input}", "--save-as", "{input_dir}/{stem}.png"]
+        outputs = ["{input_dir}/{stem}.png"]
+        publish_source = true
+        "#;
+
+        let config = DexterousConfig::load_toml_from_str(toml).expect("Couldn't load toml");
+        let build_settings = config
+            .generate_build_settings(None, &[])
+            .expect("Couldn't generate build settings");
+
+        let (_, settings) = build_settings.first().expect("No Targets Set Up");
+
+        assert_eq!(settings.asset_processors.len(), 2);
+        assert_eq!(settings.asset_processors[0].globs, vec!["**/*.ase"]);
+        assert!(settings.asset_processors[0].publish_source);
+        assert_eq!(settings.asset_processors[1].globs, vec!["**/*.wgsl"]);
+        assert_eq!(
+            settings.asset_processors[1].outputs,
+            vec!["{input_dir}/{stem}.spv"]
+        );
+        assert!(!settings.asset_processors[1].publish_source);
+    }
 }
@@ -3,7 +3,7 @@ pub mod cargo_path_utils;
 #[cfg(feature = "config")]
 pub mod config;
 
-use std::{collections::HashMap, fmt::Display, ops::Deref, str::FromStr};
+use std::{collections::HashMap, fmt::Display, ops::Deref, str::FromStr, time::Duration};
 
 use camino::Utf8PathBuf;
 use serde::{de, Deserialize, Deserializer, Serialize};
@@ -32,12 +32,29 @@ pub enum PackageOrExample {
     DefaulPackage,
     Package(String),
     Example(String),
+    Bin(String),
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum BuilderTypes {
     #[default]
     Default,
+    Zig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatcherBackend {
+    #[default]
+    Native,
+    Poll,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InFlightBuildPolicy {
+    #[default]
+    Complete,
+    Restart,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -49,9 +66,155 @@ pub struct TargetBuildSettings {
     pub asset_folders: Vec<camino::Utf8PathBuf>,
     pub code_watch_folders: Vec<camino::Utf8PathBuf>,
     pub environment: HashMap<String, String>,
+    pub runtime_environment: HashMap<String, String>,
     pub builder: BuilderTypes,
     pub additional_library_directories: Vec<Utf8PathBuf>,
     pub apple_sdk_directory: Vec<Utf8PathBuf>,
+    pub in_flight_build: InFlightBuildPolicy,
+    pub profile: Option<String>,
+    pub opt_level: Option<OptLevel>,
+    pub retained_builds: Option<usize>,
+    pub linker: Linker,
+    pub toolchain: Option<String>,
+    pub reloadable_crates: Vec<String>,
+    pub check_before_build: bool,
+    pub asset_processors: Vec<AssetProcessor>,
+}
+
+/// Converts source assets matching `globs` by running `command`, and publishes the generated `outputs` to runners.
+/// Arguments and outputs can use `{input}`, `{input_dir}`, `{stem}` and `{name}`, taken from the asset's path relative to the working directory.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AssetProcessor {
+    pub globs: Vec<String>,
+    pub command: Vec<String>,
+    #[serde(default)]
+    pub outputs: Vec<String>,
+    /// Whether the source asset is published alongside the outputs
+    #[serde(default)]
+    pub publish_source: bool,
+}
+
+impl TargetBuildSettings {
+    pub fn profile(&self) -> &str {
+        self.profile.as_deref().unwrap_or("dev")
+    }
+
+    pub fn profile_directory(&self) -> &str {
+        profile_directory(self.profile())
+    }
+}
+
+pub fn profile_directory(profile: &str) -> &str {
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        profile => profile,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum OptLevel {
+    Level(u8),
+    Named(String),
+}
+
+impl Display for OptLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptLevel::Level(level) => write!(f, "{level}"),
+            OptLevel::Named(name) => f.write_str(name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Linker {
+    #[default]
+    Default,
+    System,
+    Lld,
+    Mold,
+    Custom(Utf8PathBuf),
+}
+
+impl Linker {
+    pub fn rustflags(&self, target: Target) -> Vec<String> {
+        match self {
+            Linker::Default => {
+                if matches!(target, Target::Linux | Target::LinuxArm | Target::Windows) {
+                    Linker::Lld.rustflags(target)
+                } else {
+                    vec![]
+                }
+            }
+            Linker::System => vec![],
+            Linker::Lld => vec!["-Clink-arg=-fuse-ld=lld".to_string()],
+            Linker::Mold => vec!["-Clink-arg=-fuse-ld=mold".to_string()],
+            Linker::Custom(path) => vec![format!("-Clinker={path}")],
+        }
+    }
+}
+
+impl Display for Linker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Linker::Default => f.write_str("default"),
+            Linker::System => f.write_str("system"),
+            Linker::Lld => f.write_str("lld"),
+            Linker::Mold => f.write_str("mold"),
+            Linker::Custom(path) => write!(f, "custom:{path}"),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LinkerParseError {
+    #[error("Unknown linker {0} - expected default, system, lld, mold or custom:<path>")]
+    UnknownLinker(String),
+    #[error("Custom linker is missing a path - expected custom:<path>")]
+    MissingPath,
+}
+
+impl FromStr for Linker {
+    type Err = LinkerParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(path) = s.strip_prefix("custom:") {
+            let path = path.trim();
+            if path.is_empty() {
+                return Err(LinkerParseError::MissingPath);
+            }
+            return Ok(Self::Custom(Utf8PathBuf::from(path)));
+        }
+        Ok(match s {
+            "default" => Self::Default,
+            "system" => Self::System,
+            "lld" => Self::Lld,
+            "mold" => Self::Mold,
+            unknown => return Err(LinkerParseError::UnknownLinker(unknown.to_string())),
+        })
+    }
+}
+
+impl Serialize for Linker {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Linker {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(de::Error::custom)
+    }
 }
 
 #[repr(C)]
@@ -197,6 +360,110 @@ impl FromStr for Target {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticLevel {
+    InternalCompilerError,
+    Error,
+    Warning,
+    FailureNote,
+    Note,
+    Help,
+    Unknown,
+}
+
+impl DiagnosticLevel {
+    pub const fn is_error(&self) -> bool {
+        matches!(self, Self::Error | Self::InternalCompilerError)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticSpan {
+    pub file_name: Utf8PathBuf,
+    pub line_start: usize,
+    pub line_end: usize,
+    pub column_start: usize,
+    pub column_end: usize,
+    pub is_primary: bool,
+    pub label: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BuildDiagnostic {
+    pub level: DiagnosticLevel,
+    pub message: String,
+    pub code: Option<String>,
+    pub spans: Vec<DiagnosticSpan>,
+    pub children: Vec<BuildDiagnostic>,
+    pub rendered: Option<String>,
+}
+
+impl BuildDiagnostic {
+    pub fn primary_span(&self) -> Option<&DiagnosticSpan> {
+        self.spans.iter().find(|span| span.is_primary)
+    }
+}
+
+impl Display for BuildDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(rendered) = &self.rendered {
+            return f.write_str(rendered);
+        }
+        let level = format!("{:?}", self.level).to_lowercase();
+        match &self.code {
+            Some(code) => write!(f, "{level}[{code}]: {}", self.message)?,
+            None => write!(f, "{level}: {}", self.message)?,
+        }
+        if let Some(span) = self.primary_span() {
+            write!(
+                f,
+                "\n  --> {}:{}:{}",
+                span.file_name, span.line_start, span.column_start
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BuildTimings {
+    pub metadata: Duration,
+    pub check: Duration,
+    pub compilation: Duration,
+    pub dependency_scan: Duration,
+    pub hashing: Duration,
+    /// From the build completing until the slowest runner reported loading it.
+    /// It's filled in by the manager as runners report in, so it's zero when the timings are first sent.
+    pub broadcast: Duration,
+}
+
+impl BuildTimings {
+    pub fn total(&self) -> Duration {
+        self.metadata
+            + self.check
+            + self.compilation
+            + self.dependency_scan
+            + self.hashing
+            + self.broadcast
+    }
+}
+
+impl Display for BuildTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "metadata {:?}, check {:?}, compilation {:?}, dependency scan {:?}, hashing {:?}, broadcast {:?} - total {:?}",
+            self.metadata,
+            self.check,
+            self.compilation,
+            self.dependency_scan,
+            self.hashing,
+            self.broadcast,
+            self.total()
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum HotReloadMessage {
     InitialState {
@@ -207,13 +474,39 @@ pub enum HotReloadMessage {
         most_recent_started_build: u32,
         most_recent_completed_build: u32,
         builder_type: BuilderTypes,
+        environment: HashMap<String, String>,
     },
     UpdatedAssets(Utf8PathBuf, [u8; 32]),
     KeepAlive,
     BuildStarted(u32),
+    BuildCancelled(u32),
     BuildCompleted {
         id: u32,
         libraries: Vec<(String, [u8; 32], Vec<String>)>,
         root_library: String,
     },
+    BuildDiagnostics {
+        id: u32,
+        diagnostics: Vec<BuildDiagnostic>,
+    },
+    BuildTimings {
+        id: u32,
+        timings: BuildTimings,
+    },
+    BuildQueued {
+        id: u32,
+        position: usize,
+    },
+    RemovedAsset(Utf8PathBuf),
+    RenamedAsset {
+        from: Utf8PathBuf,
+        to: Utf8PathBuf,
+        hash: [u8; 32],
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum HotReloadRunnerMessage {
+    LoadedBuild(u32),
+    LoadedRoot(u32),
 }